@@ -1,57 +1,205 @@
-use std::time::Duration;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use crate::mini_chain::{
     address::Address,
+    chain::Receipt,
     metadata::{ChainMetaData, ChainMetaDataOperation},
-    transaction::Transaction,
+    node::{Node, ReceiptProvider, TxProcesser},
+    transaction::{Transaction, TxStatus},
 };
 use async_channel::Sender;
 use async_trait::async_trait;
+use rand::{seq::SliceRandom, Rng};
 
 #[derive(Debug, Clone)]
 pub struct Client {
     addr: Address,
+    recipients: Vec<String>,
+    nonce: Arc<AtomicUsize>,
     net_tx_sender: Sender<Transaction>,
+    // Node the client asks about its transactions.
+    node: Option<Node>,
 }
 
 impl Client {
-    pub fn new(tx_sender: Sender<Transaction>) -> Self {
-        let new_addr = Address::new();
-
+    pub fn new(addr: Address, tx_sender: Sender<Transaction>) -> Self {
         Self {
-            addr: new_addr,
+            addr,
+            recipients: vec![],
+            nonce: Arc::new(AtomicUsize::new(0)),
             net_tx_sender: tx_sender,
+            node: None,
+        }
+    }
+
+    pub fn get_public_address(&self) -> &str {
+        self.addr.get_public_address()
+    }
+
+    pub fn get_nonce(&self) -> usize {
+        self.nonce.load(Ordering::SeqCst)
+    }
+
+    pub fn set_recipients(&mut self, recipients: Vec<String>) {
+        self.recipients = recipients
+            .into_iter()
+            .filter(|addr| addr != self.addr.get_public_address())
+            .collect();
+    }
+
+    pub fn set_node(&mut self, node: Node) {
+        self.node = Some(node);
+    }
+
+    fn node(&self) -> Result<&Node, String> {
+        self.node
+            .as_ref()
+            .ok_or("Client is not connected to a node".to_string())
+    }
+
+    // Waits for the client's node to take the transaction into its mempool. Gives up after
+    // `block_gen_period` without an answer.
+    async fn wait_for_admission(&self, tx_hash: &str) -> Result<(), String> {
+        let block_gen_period = ChainMetaData::default().get_block_gen_period()?;
+        let deadline = Instant::now() + Duration::from_millis(block_gen_period as u64);
+        loop {
+            match self.node()?.get_transaction_status(tx_hash).await {
+                Some(TxStatus::REJECTED(reason)) => {
+                    return Err(format!("Transaction {} was rejected: {:?}", tx_hash, reason));
+                }
+                Some(_) => return Ok(()),
+                None if Instant::now() >= deadline => {
+                    return Err(format!("Transaction {} did not reach the mempool", tx_hash));
+                }
+                None => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
         }
     }
 }
 
 #[async_trait]
 pub trait TxTrigger {
+    async fn send_transaction(
+        &self,
+        to_addr: String,
+        amount: usize,
+        fee: usize,
+        nonce: usize,
+    ) -> Result<Transaction, String>;
     async fn rand_tx_trigger(&self) -> Result<(), String>;
+    async fn replace_transaction(
+        &self,
+        nonce: usize,
+        to_addr: String,
+        amount: usize,
+        fee: usize,
+    ) -> Result<Transaction, String>;
+    async fn cancel_transaction(&self, nonce: usize, fee: usize) -> Result<Transaction, String>;
 }
 
 #[async_trait]
 impl TxTrigger for Client {
-    async fn rand_tx_trigger(&self) -> Result<(), String> {
-        // let mut rnd = rand::thread_rng();
-        // let amount = rnd.gen_range(0..100);
-        let amount = 20;
-
-        let mut new_tx = Transaction::new(String::new(), amount);
+    async fn send_transaction(
+        &self,
+        to_addr: String,
+        amount: usize,
+        fee: usize,
+        nonce: usize,
+    ) -> Result<Transaction, String> {
+        let chain_id = self.node()?.chain_id;
+        let mut new_tx = Transaction::new(chain_id, to_addr, amount, fee, nonce);
 
-        new_tx.sign_transaction(
-            self.addr.get_public_address().to_string(),
-            self.addr.get_signature(),
-        );
+        new_tx.sign_transaction(&self.addr);
 
-        let _ = self
-            .net_tx_sender
-            .send(new_tx)
+        self.net_tx_sender
+            .send(new_tx.clone())
             .await
             .map_err(|e| e.to_string())?;
 
+        Ok(new_tx)
+    }
+
+    async fn rand_tx_trigger(&self) -> Result<(), String> {
+        let (to_addr, amount, fee) = {
+            let mut rnd = rand::thread_rng();
+            let to_addr = self.recipients.choose(&mut rnd).cloned().unwrap_or_default();
+            (to_addr, rnd.gen_range(1..100), rnd.gen_range(1..10))
+        };
+
+        // The nonce is taken from the node before every send, so it catches up after a restart or a
+        // transaction the node dropped, and only moves on once the transaction is admitted.
+        let nonce = self
+            .node()?
+            .get_next_nonce(self.get_public_address())
+            .await;
+        self.nonce.store(nonce, Ordering::SeqCst);
+        let tx = self.send_transaction(to_addr, amount, fee, nonce).await?;
+        self.wait_for_admission(&tx.hash).await?;
+        let _ = self
+            .nonce
+            .compare_exchange(nonce, nonce + 1, Ordering::SeqCst, Ordering::SeqCst);
+
         Ok(())
     }
+
+    // Signs a new transaction for an already used nonce. Mempools swap it in for the pending one
+    // when the fee is high enough, see `replacement_fee_bump`.
+    async fn replace_transaction(
+        &self,
+        nonce: usize,
+        to_addr: String,
+        amount: usize,
+        fee: usize,
+    ) -> Result<Transaction, String> {
+        if nonce >= self.get_nonce() {
+            return Err(format!("Nonce {} has not been used yet", nonce));
+        }
+        self.send_transaction(to_addr, amount, fee, nonce).await
+    }
+
+    // Replaces the pending transaction with an empty transfer to the client itself.
+    async fn cancel_transaction(&self, nonce: usize, fee: usize) -> Result<Transaction, String> {
+        let own_addr = self.get_public_address().to_string();
+        self.replace_transaction(nonce, own_addr, 0, fee).await
+    }
+}
+
+#[async_trait]
+pub trait TxTracker {
+    async fn get_receipt(&self, tx_hash: &str) -> Result<Option<Receipt>, String>;
+    async fn get_confirmations(&self, tx_hash: &str) -> Result<u64, String>;
+    async fn wait_for_confirmations(
+        &self,
+        tx_hash: &str,
+        confirmations: u64,
+    ) -> Result<Receipt, String>;
+}
+
+#[async_trait]
+impl TxTracker for Client {
+    async fn get_receipt(&self, tx_hash: &str) -> Result<Option<Receipt>, String> {
+        Ok(self.node()?.get_receipt(tx_hash).await)
+    }
+
+    async fn get_confirmations(&self, tx_hash: &str) -> Result<u64, String> {
+        Ok(self.node()?.get_confirmations(tx_hash).await)
+    }
+
+    async fn wait_for_confirmations(
+        &self,
+        tx_hash: &str,
+        confirmations: u64,
+    ) -> Result<Receipt, String> {
+        self.node()?
+            .wait_for_confirmations(tx_hash, confirmations)
+            .await
+    }
 }
 
 #[async_trait]
@@ -1,49 +1,100 @@
-use std::fmt;
-use sha3::{Digest, Sha3_256};
-use rand::{distributions::Alphanumeric, Rng};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use std::{fmt, fs, path::Path};
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct Address(String, String);
+pub struct Address {
+    keypair: SigningKey,
+    public_address: String,
+}
 
 impl fmt::Display for Address {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}:{}", self.0, self.1)
+        write!(f, "{}", self.public_address)
+    }
+}
+
+impl Default for Address {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl Address {
     pub fn new() -> Self {
-        let pub_addr: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(40)
-            .map(char::from)
-            .collect();
+        Address::from_signing_key(SigningKey::generate(&mut OsRng))
+    }
+
+    // Reads the secret key kept at `path`, or generates one and writes it there, so the address
+    // stays the same across restarts.
+    pub fn load_or_create(path: &str) -> Result<Self, String> {
+        if let Ok(secret) = fs::read_to_string(path) {
+            let key_bytes: [u8; 32] = hex::decode(secret.trim())
+                .map_err(|e| format!("{}: {}", path, e))?
+                .try_into()
+                .map_err(|_| format!("{}: invalid secret key length", path))?;
+            return Ok(Address::from_signing_key(SigningKey::from_bytes(&key_bytes)));
+        }
 
-        let pri_addr: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(64)
-            .map(char::from)
-            .collect();
+        if let Some(dir) = Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let address = Address::new();
+        fs::write(path, hex::encode(address.keypair.to_bytes())).map_err(|e| e.to_string())?;
+        Ok(address)
+    }
 
-        Address(pub_addr, pri_addr)
+    fn from_signing_key(keypair: SigningKey) -> Self {
+        let public_address = hex::encode(keypair.verifying_key().as_bytes());
+        Address {
+            keypair,
+            public_address,
+        }
     }
 
     pub fn get_public_address(&self) -> &str {
-        &self.0
+        &self.public_address
     }
 
-    pub fn get_signature(&self) -> String {
-        let mut hasher = Sha3_256::new();
+    pub fn sign(&self, data: &[u8]) -> String {
+        hex::encode(self.keypair.sign(data).to_bytes())
+    }
+}
 
-        let data = format!("{}{}", &self.0, &self.1);
+// The public address is the hex encoded Ed25519 public key, so anyone can check a signature with it.
+pub fn verify_signature(public_address: &str, data: &[u8], signature: &str) -> Result<(), String> {
+    let key_bytes: [u8; 32] = hex::decode(public_address)
+        .map_err(|e| e.to_string())?
+        .try_into()
+        .map_err(|_| "Invalid public address length".to_string())?;
+    let verifying_key = VerifyingKey::from_bytes(&key_bytes).map_err(|e| e.to_string())?;
 
-        hasher.update(data);
+    let sig_bytes: [u8; 64] = hex::decode(signature)
+        .map_err(|e| e.to_string())?
+        .try_into()
+        .map_err(|_| "Invalid signature length".to_string())?;
+    let signature = Signature::from_bytes(&sig_bytes);
 
-        let hash = format!("{:x}", hasher.finalize());
-        hash
-    }
+    verifying_key
+        .verify(data, &signature)
+        .map_err(|e| e.to_string())
+}
 
-    // pub fn get_private_address(&self) -> &str {
-    //     &self.1
-    // }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_stored_key_is_reloaded() {
+        let path = std::env::temp_dir()
+            .join(format!("mini-chain-address-{}", std::process::id()))
+            .join("node.key");
+        let _ = fs::remove_file(&path);
+        let path = path.to_string_lossy().to_string();
+
+        let created = Address::load_or_create(&path).unwrap();
+        let loaded = Address::load_or_create(&path).unwrap();
+        assert_eq!(loaded.get_public_address(), created.get_public_address());
+        assert!(verify_signature(created.get_public_address(), b"data", &loaded.sign(b"data")).is_ok());
+    }
 }
@@ -1,15 +1,31 @@
-use super::transaction::Transaction;
+use super::{
+    codec::{self, Decode, Encode, Reader},
+    merkle::{self, MerkleProof},
+    transaction::Transaction,
+};
+use sha3::{Digest, Sha3_256};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Clone)]
-pub struct Block {
+#[derive(Debug, PartialEq, Clone)]
+pub struct BlockHeader {
+    // Derived from the genesis block, so a block only ever validates on the chain it was built for.
+    chain_id: u64,
     builder: Option<String>,
     sequence: Option<u64>,
     timestamp: usize,
     tx_count: usize,
-    transactions: Vec<Transaction>,
+    merkle_root: String,
+    bits: u32,
     nonce: usize,
     prev_hash: String,
+    // Addresses whose votes finalize blocks. Only the genesis block sets them.
+    validators: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block {
+    header: BlockHeader,
+    transactions: Vec<Transaction>,
     hash: String,
 }
 
@@ -20,63 +36,176 @@ impl Default for Block {
             .unwrap()
             .as_secs() as usize;
         Self {
-            builder: None,
-            sequence: None,
-            timestamp: timestamp,
-            tx_count: 0,
+            header: BlockHeader {
+                chain_id: 0,
+                builder: None,
+                sequence: None,
+                timestamp,
+                tx_count: 0,
+                merkle_root: merkle::merkle_root(&[]),
+                bits: 0,
+                nonce: 0,
+                prev_hash: String::new(),
+                validators: vec![],
+            },
             transactions: vec![],
-            nonce: 0,
-            prev_hash: String::new(),
             hash: String::new(),
         }
     }
 }
 
-impl Block {
-    pub fn builder(&self) -> Option<String> { 
-        match &self.builder {
-            Some(addr) => Some(addr.clone()),
-            None => None,
-        }
-    }
+impl BlockHeader {
+    pub fn chain_id(&self) -> u64 { self.chain_id }
+    pub fn builder(&self) -> Option<String> { self.builder.clone() }
     pub fn sequence(&self) -> Option<u64> { self.sequence }
     pub fn timestamp(&self) -> usize { self.timestamp }
     pub fn tx_count(&self) -> usize { self.tx_count }
-    pub fn transactions(&self) -> Vec<Transaction> { self.transactions.clone() }
+    pub fn merkle_root(&self) -> String { self.merkle_root.clone() }
+    pub fn bits(&self) -> u32 { self.bits }
     pub fn nonce(&self) -> usize { self.nonce }
     pub fn prev_hash(&self) -> String { self.prev_hash.clone() }
+    pub fn validators(&self) -> Vec<String> { self.validators.clone() }
+    pub fn set_nonce(&mut self, nonce: usize) { self.nonce = nonce; }
+    pub fn calculate_hash(&self) -> String {
+        format!("{:x}", Sha3_256::digest(codec::to_bytes(self)))
+    }
+}
+
+impl Block {
+    pub fn header(&self) -> BlockHeader { self.header.clone() }
+    pub fn chain_id(&self) -> u64 { self.header.chain_id() }
+    pub fn builder(&self) -> Option<String> { self.header.builder() }
+    pub fn sequence(&self) -> Option<u64> { self.header.sequence() }
+    pub fn timestamp(&self) -> usize { self.header.timestamp() }
+    pub fn tx_count(&self) -> usize { self.header.tx_count() }
+    pub fn merkle_root(&self) -> String { self.header.merkle_root() }
+    pub fn bits(&self) -> u32 { self.header.bits() }
+    pub fn transactions(&self) -> Vec<Transaction> { self.transactions.clone() }
+    pub fn nonce(&self) -> usize { self.header.nonce() }
+    pub fn prev_hash(&self) -> String { self.header.prev_hash() }
+    pub fn validators(&self) -> Vec<String> { self.header.validators() }
     pub fn hash(&self) -> String { self.hash.clone() }
-    pub fn inc_nonce(&mut self) { self.nonce += 1; }
+    pub fn inc_nonce(&mut self) { self.header.nonce += 1; }
+    // Bytes covered by the block hash: only the header, which commits to the body through the merkle root.
+    pub fn hashing_bytes(&self) -> Vec<u8> {
+        codec::to_bytes(&self.header)
+    }
+    pub fn calculate_hash(&self) -> String {
+        self.header.calculate_hash()
+    }
     pub fn tx_hashes(&self) -> Vec<String> {
         let hashes: Vec<String> = self.transactions.iter().map(|tx| tx.hash.clone()).collect();
         hashes
     }
+    pub fn calculate_merkle_root(&self) -> String {
+        merkle::merkle_root(&self.tx_hashes())
+    }
+    pub fn merkle_proof(&self, tx_hash: &str) -> Result<MerkleProof, String> {
+        merkle::merkle_proof(&self.tx_hashes(), tx_hash)
+            .ok_or(format!("Transaction {} is not in block {}", tx_hash, self.hash))
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.chain_id.encode(buf);
+        self.builder.encode(buf);
+        self.sequence.encode(buf);
+        self.timestamp.encode(buf);
+        self.tx_count.encode(buf);
+        self.merkle_root.encode(buf);
+        self.bits.encode(buf);
+        self.nonce.encode(buf);
+        self.prev_hash.encode(buf);
+        self.validators.encode(buf);
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Self {
+            chain_id: u64::decode(reader)?,
+            builder: Option::<String>::decode(reader)?,
+            sequence: Option::<u64>::decode(reader)?,
+            timestamp: usize::decode(reader)?,
+            tx_count: usize::decode(reader)?,
+            merkle_root: String::decode(reader)?,
+            bits: u32::decode(reader)?,
+            nonce: usize::decode(reader)?,
+            prev_hash: String::decode(reader)?,
+            validators: Vec::<String>::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for Block {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.header.encode(buf);
+        self.transactions.encode(buf);
+        self.hash.encode(buf);
+    }
+}
+
+impl Decode for Block {
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Self {
+            header: BlockHeader::decode(reader)?,
+            transactions: Vec::<Transaction>::decode(reader)?,
+            hash: String::decode(reader)?,
+        })
+    }
 }
 
 pub trait BlockConfigurer {
     fn add_transaction(&mut self, tx: Transaction);
+    fn set_chain_id(&mut self, chain_id: u64);
     fn set_block_builder(&mut self, addr: String);
     fn set_block_sequence(&mut self, seq: u64);
+    fn set_timestamp(&mut self, timestamp: usize);
+    fn set_bits(&mut self, bits: u32);
+    fn set_nonce(&mut self, nonce: usize);
     fn set_prev_hash(&mut self, prev_hash: String);
+    fn set_validators(&mut self, validators: Vec<String>);
     fn set_hash(&mut self, hash: String);
 }
 
 impl BlockConfigurer for Block {
     fn add_transaction(&mut self, tx: Transaction) {
-        self.transactions.insert(0, tx);
-        self.tx_count += 1;
+        self.transactions.push(tx);
+        self.header.tx_count += 1;
+        self.header.merkle_root = self.calculate_merkle_root();
+    }
+
+    fn set_chain_id(&mut self, chain_id: u64) {
+        self.header.chain_id = chain_id;
     }
 
     fn set_block_builder(&mut self, addr: String) {
-        self.builder = Some(addr);
+        self.header.builder = Some(addr);
     }
 
     fn set_block_sequence(&mut self, seq: u64) {
-        self.sequence = Some(seq);
+        self.header.sequence = Some(seq);
+    }
+
+    fn set_timestamp(&mut self, timestamp: usize) {
+        self.header.timestamp = timestamp;
+    }
+
+    fn set_bits(&mut self, bits: u32) {
+        self.header.bits = bits;
+    }
+
+    fn set_nonce(&mut self, nonce: usize) {
+        self.header.set_nonce(nonce);
     }
 
     fn set_prev_hash(&mut self, prev_hash: String) {
-        self.prev_hash = prev_hash;
+        self.header.prev_hash = prev_hash;
+    }
+
+    fn set_validators(&mut self, validators: Vec<String>) {
+        self.header.validators = validators;
     }
 
     fn set_hash(&mut self, hash: String) {
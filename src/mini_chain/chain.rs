@@ -1,44 +1,890 @@
-use super::block::Block;
-use std::collections::HashMap;
+use super::{
+    block::Block,
+    genesis::{self, GenesisConfig},
+    metadata::{ChainMetaData, ChainMetaDataOperation},
+    pow,
+    state::{Account, WorldState, WorldStateOperation},
+    transaction::TxStatus,
+};
+use primitive_types::U256;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 #[derive(Debug, Clone)]
+struct ChainEntry {
+    block: Block,
+    height: u64,
+    total_work: U256,
+}
+
+#[derive(Debug, Clone)]
+struct OrphanBlock {
+    block: Block,
+    received_at: Instant,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Reorg {
+    pub old_tip: String,
+    pub new_tip: String,
+    pub common_ancestor: String,
+    // Blocks that left the canonical chain and the ones that joined it, oldest first.
+    pub disconnected: Vec<Block>,
+    pub connected: Vec<Block>,
+}
+
+// Where a transaction of the canonical chain was included.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Receipt {
+    pub tx_hash: String,
+    pub block_hash: String,
+    pub height: u64,
+    pub index: usize,
+    pub status: TxStatus,
+    pub fee: usize,
+}
+
+// Walks from a block back to the genesis block, the starting block included.
+pub struct Ancestors<'a> {
+    chain: &'a Blockchain,
+    cursor: Option<String>,
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = &'a Block;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.chain.blocks.get(&self.cursor.take()?)?;
+        if entry.height > 0 {
+            self.cursor = Some(entry.block.prev_hash());
+        }
+        Some(&entry.block)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ChainUpdate {
+    Extended(String),
+    SideBranch(String),
+    Reorganized(Reorg),
+    // The block waits in the orphan pool for the parent carried here.
+    Orphaned(String),
+    AlreadyKnown,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Blockchain {
-    blocks: HashMap<String, Block>,
+    chain_id: u64,
+    genesis_hash: String,
+    validators: Vec<String>,
+    // Latest block finalized by a quorum of validators. It and its ancestors are never disconnected.
+    finalized: String,
+    blocks: HashMap<String, ChainEntry>,
+    tips: HashSet<String>,
+    orphans: HashMap<String, OrphanBlock>,
     leaf: String,
-    sequence: u64
+    // Hashes of the canonical chain, indexed by height.
+    canonical: Vec<String>,
+    state: WorldState,
+    // Receipts of the canonical chain's transactions, keyed by transaction hash.
+    receipts: HashMap<String, Receipt>,
 }
 
-impl Default for Blockchain {
-    fn default() -> Self {
-        Self {
-            blocks: HashMap::new(),
-            leaf: String::new(),
-            sequence: 0,
+// Newly minted coins for a block at `height`, halved every `subsidy_halving_interval` blocks.
+pub fn block_subsidy(height: u64) -> Result<usize, String> {
+    let (subsidy, halving_interval) = {
+        let chain_metadata = ChainMetaData::default();
+        (
+            chain_metadata.get_block_subsidy()?,
+            chain_metadata.get_subsidy_halving_interval()?,
+        )
+    };
+
+    if halving_interval == 0 {
+        return Ok(subsidy);
+    }
+    let halvings = height / halving_interval as u64;
+    Ok(subsidy.checked_shr(halvings.try_into().unwrap_or(u32::MAX)).unwrap_or(0))
+}
+
+impl Blockchain {
+    // Starts the tree at the genesis block, with the allocations as the initial state.
+    pub fn new(genesis: &GenesisConfig) -> Self {
+        let genesis_block = genesis.genesis_block();
+        let genesis_hash = genesis_block.hash();
+        let mut chain = Self {
+            chain_id: genesis::chain_id_from_hash(&genesis_hash),
+            genesis_hash: genesis_hash.clone(),
+            validators: genesis_block.validators(),
+            finalized: genesis_hash.clone(),
+            leaf: genesis_hash.clone(),
+            canonical: vec![genesis_hash.clone()],
+            state: WorldState::new(genesis.allocations.clone()),
+            ..Default::default()
+        };
+        chain.blocks.insert(
+            genesis_hash.clone(),
+            ChainEntry {
+                block: genesis_block,
+                height: 0,
+                total_work: U256::zero(),
+            },
+        );
+        chain.tips.insert(genesis_hash);
+        chain
+    }
+
+    fn total_work(&self, hash: &str) -> U256 {
+        self.blocks
+            .get(hash)
+            .map_or(U256::zero(), |entry| entry.total_work)
+    }
+
+    // Hashes from the root of the tree down to `hash`, both included.
+    fn path_to(&self, hash: &str) -> Vec<String> {
+        let mut path: Vec<String> = self.get_ancestors(hash).map(|block| block.hash()).collect();
+        path.reverse();
+        path
+    }
+
+    // Whether `ancestor` is `hash` itself or one of its ancestors.
+    fn descends_from(&self, hash: &str, ancestor: &str) -> bool {
+        let Some(ancestor_height) = self.get_height(ancestor) else {
+            return false;
+        };
+        self.get_ancestors(hash)
+            .find(|block| block.sequence() <= Some(ancestor_height))
+            .is_some_and(|block| block.hash() == ancestor)
+    }
+
+    fn common_ancestor(&self, a: &str, b: &str) -> String {
+        let path_a = self.path_to(a);
+        let path_b = self.path_to(b);
+        path_a
+            .iter()
+            .zip(path_b.iter())
+            .take_while(|(x, y)| x == y)
+            .last()
+            .map(|(x, _)| x.clone())
+            .unwrap_or_default()
+    }
+
+    // Blocks after `ancestor` up to and including `hash`, oldest first.
+    fn branch_from(&self, ancestor: &str, hash: &str) -> Vec<Block> {
+        let path = self.path_to(hash);
+        let start = path.iter().position(|h| h == ancestor).map_or(0, |pos| pos + 1);
+        path[start..]
+            .iter()
+            .map(|h| self.blocks[h].block.clone())
+            .collect()
+    }
+
+    fn index_receipts(&mut self, hash: &str) {
+        let Some(entry) = self.blocks.get(hash) else {
+            return;
+        };
+        for (index, tx) in entry.block.transactions().into_iter().enumerate() {
+            let receipt = Receipt {
+                tx_hash: tx.hash.clone(),
+                block_hash: hash.to_string(),
+                height: entry.height,
+                index,
+                status: TxStatus::APPROVED {
+                    block_hash: hash.to_string(),
+                    height: entry.height,
+                },
+                fee: tx.fee,
+            };
+            self.receipts.insert(tx.hash, receipt);
         }
     }
+
+    fn unindex_receipts(&mut self, block: &Block) {
+        for tx_hash in block.tx_hashes() {
+            self.receipts.remove(&tx_hash);
+        }
+    }
+
+    // Drops expired orphans and, when the pool is still full, the oldest one.
+    fn make_orphan_room(&mut self) -> Result<(), String> {
+        let (pool_size, expiry) = {
+            let chain_metadata = ChainMetaData::default();
+            (
+                chain_metadata.get_orphan_pool_size()?,
+                chain_metadata.get_orphan_block_expiry()?,
+            )
+        };
+
+        let expiry = Duration::from_millis(expiry as u64);
+        self.orphans
+            .retain(|_, orphan| orphan.received_at.elapsed() < expiry);
+
+        while self.orphans.len() >= pool_size {
+            let oldest = self
+                .orphans
+                .iter()
+                .min_by_key(|(_, orphan)| orphan.received_at)
+                .map(|(hash, _)| hash.clone());
+            match oldest {
+                Some(hash) => self.orphans.remove(&hash),
+                None => break,
+            };
+        }
+        Ok(())
+    }
+
+    // Rolls the canonical state back to the common ancestor and replays the branch up to `hash`.
+    fn state_at(&self, hash: &str) -> Result<WorldState, String> {
+        let ancestor = self.common_ancestor(&self.leaf, hash);
+        let mut state = self.state.clone();
+        for block in self.branch_from(&ancestor, &self.leaf).iter().rev() {
+            state.revert_block(block)?;
+        }
+        for block in self.branch_from(&ancestor, hash).iter() {
+            state.apply_block(block)?;
+        }
+        Ok(state)
+    }
 }
 
 pub trait BlockchainOperation {
-    fn add_block(&mut self, block: Block) -> Result<(), String>;
+    fn add_block(&mut self, block: Block) -> Result<ChainUpdate, String>;
+    fn next_bits(&self, prev_hash: &str) -> Result<u32, String>;
+    fn verify_coinbase(&self, block: &Block) -> Result<(), String>;
+    fn verify_timestamp(&self, block: &Block) -> Result<(), String>;
+    fn connect_orphans(&mut self, hash: &str) -> Vec<(Block, ChainUpdate)>;
+    fn take_orphans(&mut self, parent: &str) -> Vec<Block>;
+    fn is_missing_parent(&self, hash: &str) -> bool;
+    fn contains_block(&self, hash: &str) -> bool;
+    fn get_height(&self, hash: &str) -> Option<u64>;
+    fn get_block_by_hash(&self, hash: &str) -> Option<Block>;
+    fn get_block_by_height(&self, height: u64) -> Option<Block>;
+    fn get_ancestors(&self, hash: &str) -> Ancestors<'_>;
     fn get_leaf(&self) -> Result<String, String>;
-    fn get_sequence(&self) -> Result<u64, String>;
+    fn get_chain_id(&self) -> u64;
+    fn get_genesis_hash(&self) -> String;
+    fn get_validators(&self) -> Vec<String>;
+    fn get_quorum(&self) -> usize;
+    fn finalize_block(&mut self, hash: &str) -> Result<(), String>;
+    fn get_finalized(&self) -> String;
+    fn get_tips(&self) -> Result<Vec<String>, String>;
+    fn get_leaf_height(&self) -> u64;
+    fn get_state(&self) -> Result<WorldState, String>;
+    fn get_state_at(&self, hash: &str) -> Result<WorldState, String>;
+    fn get_balance(&self, addr: &str) -> Result<usize, String>;
+    fn get_account(&self, addr: &str) -> Result<Account, String>;
+    fn get_receipt(&self, tx_hash: &str) -> Option<Receipt>;
+    fn get_confirmations(&self, tx_hash: &str) -> u64;
 }
 
 impl BlockchainOperation for Blockchain {
-    fn add_block(&mut self, block: Block) -> Result<(), String> {
-        if self.blocks.contains_key(&block.prev_hash()) || self.blocks.is_empty() {
-            self.blocks.insert(block.hash(), block.clone());
-            self.leaf = block.hash();
+    fn add_block(&mut self, block: Block) -> Result<ChainUpdate, String> {
+        let hash = block.hash();
+        if self.blocks.contains_key(&hash) || self.orphans.contains_key(&hash) {
+            return Ok(ChainUpdate::AlreadyKnown);
+        }
+        if block.chain_id() != self.chain_id {
+            return Err(format!(
+                "Block {} belongs to chain {:016x}, not {:016x}",
+                hash,
+                block.chain_id(),
+                self.chain_id
+            ));
+        }
+
+        if let Some(tx) = block
+            .transactions()
+            .iter()
+            .find(|tx| tx.chain_id != self.chain_id)
+        {
+            return Err(format!(
+                "Transaction {} belongs to chain {:016x}, not {:016x}",
+                tx.hash, tx.chain_id, self.chain_id
+            ));
+        }
+
+        let prev_hash = block.prev_hash();
+        if prev_hash.is_empty() {
+            return Err(format!("Block {} claims to be a genesis block", hash));
         }
+        if !block.validators().is_empty() {
+            return Err(format!("Block {} changes the validator set", hash));
+        }
+        self.verify_timestamp(&block)?;
+        let (height, parent_work) = match self.blocks.get(&prev_hash) {
+            Some(parent) => (parent.height + 1, parent.total_work),
+            None => {
+                self.make_orphan_room()?;
+                self.orphans.insert(
+                    hash,
+                    OrphanBlock {
+                        block,
+                        received_at: Instant::now(),
+                    },
+                );
+                return Ok(ChainUpdate::Orphaned(prev_hash));
+            }
+        };
+
+        if block.sequence() != Some(height) {
+            return Err(format!(
+                "Invalid block height: expected {}, got {:?}",
+                height,
+                block.sequence()
+            ));
+        }
+
+        if !self.descends_from(&prev_hash, &self.finalized) {
+            return Err(format!(
+                "Block {} does not descend from the finalized block {}",
+                hash, self.finalized
+            ));
+        }
+
+        let bits = self.next_bits(&prev_hash)?;
+        if block.bits() != bits {
+            return Err(format!(
+                "Invalid difficulty bits: expected {:#010x}, got {:#010x}",
+                bits,
+                block.bits()
+            ));
+        }
+        self.verify_coinbase(&block)?;
+        let total_work = parent_work.saturating_add(pow::expected_hashes(bits));
+
+        let is_extension = prev_hash == self.leaf;
+        let is_reorg = !is_extension && total_work > self.total_work(&self.leaf);
+
+        // Blocks becoming canonical must apply cleanly; a heavier side branch is replayed from the fork point.
+        let next_state = if is_extension {
+            let mut state = self.state.clone();
+            state.apply_block(&block)?;
+            Some(state)
+        } else if is_reorg {
+            let mut state = self.state_at(&prev_hash)?;
+            state.apply_block(&block)?;
+            Some(state)
+        } else {
+            None
+        };
+
+        self.blocks.insert(
+            hash.clone(),
+            ChainEntry {
+                block,
+                height,
+                total_work,
+            },
+        );
+        self.tips.remove(&prev_hash);
+        self.tips.insert(hash.clone());
+
+        let Some(state) = next_state else {
+            return Ok(ChainUpdate::SideBranch(hash));
+        };
+        self.state = state;
+
+        if is_extension {
+            self.leaf = hash.clone();
+            self.canonical.push(hash.clone());
+            self.index_receipts(&hash);
+            return Ok(ChainUpdate::Extended(hash));
+        }
+
+        let common_ancestor = self.common_ancestor(&self.leaf, &hash);
+        let reorg = Reorg {
+            old_tip: self.leaf.clone(),
+            new_tip: hash.clone(),
+            disconnected: self.branch_from(&common_ancestor, &self.leaf),
+            connected: self.branch_from(&common_ancestor, &hash),
+            common_ancestor,
+        };
+        for block in reorg.disconnected.iter() {
+            self.unindex_receipts(block);
+        }
+        let ancestor_height = self.blocks[&reorg.common_ancestor].height;
+        self.canonical.truncate(ancestor_height as usize + 1);
+        for block in reorg.connected.iter() {
+            self.canonical.push(block.hash());
+            self.index_receipts(&block.hash());
+        }
+        self.leaf = hash;
+        Ok(ChainUpdate::Reorganized(reorg))
+    }
 
+    // Compact target required for a block built on `prev_hash`. Once every window the target is scaled by
+    // how long the last window took against the target interval, by at most a factor of 4 either way.
+    fn next_bits(&self, prev_hash: &str) -> Result<u32, String> {
+        let (initial_difficulty, target_interval, window) = {
+            let chain_metadata = ChainMetaData::default();
+            (
+                chain_metadata.get_block_difficulty()?,
+                chain_metadata.get_block_target_interval()?,
+                chain_metadata.get_difficulty_retarget_window()?,
+            )
+        };
+
+        let Some(parent) = self.blocks.get(prev_hash) else {
+            let target = pow::target_from_difficulty(U256::from(initial_difficulty));
+            return Ok(pow::bits_from_target(target));
+        };
+        let bits = parent.block.bits();
+        let height = parent.height + 1;
+        if window == 0 || height % window as u64 != 0 {
+            return Ok(bits);
+        }
+
+        // The genesis timestamp is fixed in the config and says nothing about mining speed, so the first
+        // window is never measured from it.
+        let path = self.path_to(prev_hash);
+        if path.len() <= window + 1 {
+            return Ok(bits);
+        }
+        let first = &self.blocks[&path[path.len() - 1 - window]].block;
+
+        let timespan = parent.block.timestamp().saturating_sub(first.timestamp());
+        pow::retarget(bits, timespan, window.saturating_mul(target_interval))
+    }
+
+    // A block opens with exactly one coinbase paying its builder no more than the subsidy at its height
+    // plus the fees of the other transactions.
+    fn verify_coinbase(&self, block: &Block) -> Result<(), String> {
+        let transactions = block.transactions();
+        let Some(coinbase) = transactions.first().filter(|tx| tx.is_coinbase()) else {
+            return Err(format!("Block {} has no coinbase", block.hash()));
+        };
+
+        let height = self.get_height(&block.prev_hash()).map_or(0, |height| height + 1);
+        if coinbase.nonce as u64 != height {
+            return Err(format!(
+                "Invalid coinbase height: expected {}, got {}",
+                height, coinbase.nonce
+            ));
+        }
+        if Some(coinbase.payload.addr.clone()) != block.builder() {
+            return Err(format!(
+                "Coinbase pays {} instead of the block builder",
+                coinbase.payload.addr
+            ));
+        }
+
+        let fees = transactions[1..]
+            .iter()
+            .fold(0usize, |fees, tx| fees.saturating_add(tx.fee));
+        let reward = block_subsidy(height)?.saturating_add(fees);
+        if coinbase.payload.amount > reward {
+            return Err(format!(
+                "Coinbase exceeds the block reward: {} > {}",
+                coinbase.payload.amount, reward
+            ));
+        }
         Ok(())
     }
 
+    // Refuses blocks over `max_future_block_time` ahead of the clock or before the median time past.
+    fn verify_timestamp(&self, block: &Block) -> Result<(), String> {
+        let (median_time_span, max_future_block_time) = {
+            let chain_metadata = ChainMetaData::default();
+            (
+                chain_metadata.get_median_time_span()?,
+                chain_metadata.get_max_future_block_time()?,
+            )
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?
+            .as_millis();
+        if block.timestamp() as u128 * 1000 > now + max_future_block_time as u128 {
+            return Err(format!(
+                "Block {} is timestamped too far in the future: {}",
+                block.hash(),
+                block.timestamp()
+            ));
+        }
+
+        // The median of the last `median_time_span` blocks is checked once the parent is known.
+        let prev_hash = block.prev_hash();
+        if !self.blocks.contains_key(&prev_hash) {
+            return Ok(());
+        }
+        let mut timestamps: Vec<usize> = self
+            .get_ancestors(&prev_hash)
+            .take(median_time_span.max(1))
+            .map(|ancestor| ancestor.timestamp())
+            .collect();
+        timestamps.sort_unstable();
+        let median_time_past = timestamps[timestamps.len() / 2];
+        // Timestamps are in seconds, so blocks sharing the median's second are allowed.
+        if block.timestamp() < median_time_past {
+            return Err(format!(
+                "Block {} predates the median time past: {} < {}",
+                block.hash(),
+                block.timestamp(),
+                median_time_past
+            ));
+        }
+        Ok(())
+    }
+
+    // Adds every orphan descending from `hash`, now that it is in the tree. Orphans that fail to apply are dropped.
+    fn connect_orphans(&mut self, hash: &str) -> Vec<(Block, ChainUpdate)> {
+        let mut connected = vec![];
+        let mut parents = vec![hash.to_string()];
+        while let Some(parent) = parents.pop() {
+            let children: Vec<String> = self
+                .orphans
+                .iter()
+                .filter(|(_, orphan)| orphan.block.prev_hash() == parent)
+                .map(|(hash, _)| hash.clone())
+                .collect();
+
+            for child in children {
+                let orphan = self.orphans.remove(&child).unwrap();
+                if let Ok(update) = self.add_block(orphan.block.clone()) {
+                    parents.push(child);
+                    connected.push((orphan.block, update));
+                }
+            }
+        }
+        connected
+    }
+
+    // Removes and returns the orphans waiting on `parent`, for callers that add them themselves.
+    fn take_orphans(&mut self, parent: &str) -> Vec<Block> {
+        let children: Vec<String> = self
+            .orphans
+            .iter()
+            .filter(|(_, orphan)| orphan.block.prev_hash() == parent)
+            .map(|(hash, _)| hash.clone())
+            .collect();
+        children
+            .into_iter()
+            .filter_map(|hash| self.orphans.remove(&hash))
+            .map(|orphan| orphan.block)
+            .collect()
+    }
+
+    fn is_missing_parent(&self, hash: &str) -> bool {
+        self.orphans
+            .values()
+            .any(|orphan| orphan.block.prev_hash() == hash)
+    }
+
+    fn contains_block(&self, hash: &str) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    fn get_height(&self, hash: &str) -> Option<u64> {
+        self.blocks.get(hash).map(|entry| entry.height)
+    }
+
+    fn get_block_by_hash(&self, hash: &str) -> Option<Block> {
+        self.blocks.get(hash).map(|entry| entry.block.clone())
+    }
+
+    // The canonical block at `height`.
+    fn get_block_by_height(&self, height: u64) -> Option<Block> {
+        let hash = self.canonical.get(usize::try_from(height).ok()?)?;
+        self.get_block_by_hash(hash)
+    }
+
+    fn get_ancestors(&self, hash: &str) -> Ancestors<'_> {
+        Ancestors {
+            chain: self,
+            cursor: Some(hash.to_string()),
+        }
+    }
+
     fn get_leaf(&self) -> Result<String, String> {
         Ok(self.leaf.clone())
     }
 
-    fn get_sequence(&self) -> Result<u64, String> {
-        Ok(self.sequence)
+    fn get_chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    fn get_genesis_hash(&self) -> String {
+        self.genesis_hash.clone()
+    }
+
+    fn get_validators(&self) -> Vec<String> {
+        self.validators.clone()
+    }
+
+    // Votes needed to finalize a block: 2f + 1 out of 3f + 1 validators, tolerating f faulty ones.
+    // For other sizes this is n - f, so that any two quorums share an honest validator.
+    fn get_quorum(&self) -> usize {
+        let validator_count = self.validators.len();
+        let faulty = validator_count.saturating_sub(1) / 3;
+        validator_count - faulty
+    }
+
+    // Finalizes a canonical block and with it all of its ancestors.
+    fn finalize_block(&mut self, hash: &str) -> Result<(), String> {
+        let Some(height) = self.get_height(hash) else {
+            return Err(format!("Unknown block {}", hash));
+        };
+        if self.canonical.get(height as usize).map(String::as_str) != Some(hash) {
+            return Err(format!("Block {} is not on the canonical chain", hash));
+        }
+        if height > self.get_height(&self.finalized).unwrap_or_default() {
+            self.finalized = hash.to_string();
+        }
+        Ok(())
+    }
+
+    fn get_finalized(&self) -> String {
+        self.finalized.clone()
+    }
+
+    fn get_tips(&self) -> Result<Vec<String>, String> {
+        Ok(self.tips.iter().cloned().collect())
+    }
+
+    fn get_leaf_height(&self) -> u64 {
+        self.canonical.len() as u64 - 1
+    }
+
+    fn get_state(&self) -> Result<WorldState, String> {
+        Ok(self.state.clone())
+    }
+
+    fn get_state_at(&self, hash: &str) -> Result<WorldState, String> {
+        if hash == self.leaf {
+            return Ok(self.state.clone());
+        }
+        if !self.blocks.contains_key(hash) {
+            return Err(format!("Unknown block {}", hash));
+        }
+        self.state_at(hash)
+    }
+
+    fn get_balance(&self, addr: &str) -> Result<usize, String> {
+        Ok(self.state.get_balance(addr))
+    }
+
+    fn get_account(&self, addr: &str) -> Result<Account, String> {
+        Ok(self.state.get_account(addr))
+    }
+
+    fn get_receipt(&self, tx_hash: &str) -> Option<Receipt> {
+        self.receipts.get(tx_hash).cloned()
+    }
+
+    // Number of canonical blocks from the one including the transaction up to the tip, 0 if not included.
+    fn get_confirmations(&self, tx_hash: &str) -> u64 {
+        self.receipts
+            .get(tx_hash)
+            .map_or(0, |receipt| self.get_leaf_height() + 1 - receipt.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mini_chain::{address::Address, block::BlockConfigurer, transaction::Transaction};
+
+    fn new_chain() -> Blockchain {
+        Blockchain::new(&GenesisConfig::new(vec![], vec![]).unwrap())
+    }
+
+    fn now() -> usize {
+        Block::default().timestamp()
+    }
+
+    // A block on `prev_hash` the chain accepts, paying its subsidy to `builder`.
+    fn child(chain: &Blockchain, prev_hash: &str, builder: &str, timestamp: usize) -> Block {
+        let height = chain.get_height(prev_hash).map_or(1, |height| height + 1);
+        let mut block = Block::default();
+        block.set_chain_id(chain.get_chain_id());
+        block.set_block_builder(builder.to_string());
+        block.set_block_sequence(height);
+        block.set_timestamp(timestamp);
+        block.add_transaction(Transaction::new_coinbase(
+            chain.get_chain_id(),
+            builder.to_string(),
+            block_subsidy(height).unwrap(),
+            height,
+        ));
+        block.set_bits(chain.next_bits(prev_hash).unwrap());
+        block.set_prev_hash(prev_hash.to_string());
+        block.set_hash(block.calculate_hash());
+        block
+    }
+
+    fn extend(chain: &mut Blockchain, prev_hash: &str, builder: &str, timestamp: usize) -> String {
+        let block = child(chain, prev_hash, builder, timestamp);
+        chain.add_block(block.clone()).unwrap();
+        block.hash()
+    }
+
+    #[test]
+    fn blocks_too_far_in_the_future_are_refused() {
+        let mut chain = new_chain();
+        let genesis_hash = chain.get_genesis_hash();
+        let block = child(&chain, &genesis_hash, "miner", now() + 3600);
+        assert!(chain.add_block(block).is_err());
+
+        // Even before their parent is known.
+        let mut orphan = child(&chain, &genesis_hash, "miner", now() + 3600);
+        orphan.set_prev_hash("unknown".to_string());
+        orphan.set_hash(orphan.calculate_hash());
+        assert!(chain.add_block(orphan).is_err());
+    }
+
+    #[test]
+    fn blocks_may_not_predate_the_median_time_past() {
+        let mut chain = new_chain();
+        let base = now() - 100;
+        let mut tip = chain.get_genesis_hash();
+        for offset in [0, 10, 20] {
+            tip = extend(&mut chain, &tip, "miner", base + offset);
+        }
+
+        // The median of genesis and the three blocks is the second block's timestamp.
+        let early = child(&chain, &tip, "miner", base + 5);
+        assert!(chain.add_block(early).is_err());
+        let before_parent = child(&chain, &tip, "miner", base + 15);
+        assert!(matches!(chain.add_block(before_parent), Ok(ChainUpdate::Extended(_))));
+    }
+
+    #[test]
+    fn oversized_or_misplaced_coinbases_are_refused() {
+        let mut chain = new_chain();
+        let genesis_hash = chain.get_genesis_hash();
+        let chain_id = chain.get_chain_id();
+        let subsidy = block_subsidy(1).unwrap();
+        let coinbase = |amount: usize| Transaction::new_coinbase(chain_id, "miner".to_string(), amount, 1);
+        let mut transfer = Transaction::new(chain_id, "payee".to_string(), 0, 0, 0);
+        transfer.sign_transaction(&Address::new());
+
+        let bits = chain.next_bits(&genesis_hash).unwrap();
+        let with_transactions = |transactions: Vec<Transaction>| {
+            let mut block = Block::default();
+            block.set_chain_id(chain_id);
+            block.set_block_builder("miner".to_string());
+            block.set_block_sequence(1);
+            block.set_timestamp(now());
+            for tx in transactions {
+                block.add_transaction(tx);
+            }
+            block.set_bits(bits);
+            block.set_prev_hash(genesis_hash.clone());
+            block.set_hash(block.calculate_hash());
+            block
+        };
+
+        let oversized = with_transactions(vec![coinbase(subsidy + 1)]);
+        assert!(chain.add_block(oversized).is_err());
+        let after_a_transfer = with_transactions(vec![transfer.clone(), coinbase(subsidy)]);
+        assert!(chain.add_block(after_a_transfer).is_err());
+        let twice = with_transactions(vec![coinbase(subsidy), coinbase(1)]);
+        assert!(chain.add_block(twice).is_err());
+
+        let valid = with_transactions(vec![coinbase(subsidy)]);
+        assert!(matches!(chain.add_block(valid), Ok(ChainUpdate::Extended(_))));
+    }
+
+    #[test]
+    fn transactions_of_another_chain_are_refused() {
+        let mut chain = new_chain();
+        let genesis_hash = chain.get_genesis_hash();
+        let mut block = child(&chain, &genesis_hash, "miner", now());
+        block.add_transaction(Transaction::new(chain.get_chain_id() + 1, "payee".to_string(), 1, 0, 0));
+        block.set_hash(block.calculate_hash());
+        assert!(chain.add_block(block).is_err());
+    }
+
+    #[test]
+    fn only_the_genesis_block_sets_validators() {
+        let mut chain = new_chain();
+        let genesis_hash = chain.get_genesis_hash();
+        let mut block = child(&chain, &genesis_hash, "miner", now());
+        block.set_validators(vec!["intruder".to_string()]);
+        block.set_hash(block.calculate_hash());
+        assert!(chain.add_block(block).is_err());
+
+        let config = GenesisConfig::new(vec![], vec!["validator".to_string()]).unwrap();
+        assert_eq!(Blockchain::new(&config).get_validators(), vec!["validator".to_string()]);
+        assert_ne!(config.chain_id(), chain.get_chain_id());
+    }
+
+    #[test]
+    fn orphans_connect_once_their_parent_arrives() {
+        let mut builder = new_chain();
+        let genesis_hash = builder.get_genesis_hash();
+        let parent = child(&builder, &genesis_hash, "miner", now());
+        builder.add_block(parent.clone()).unwrap();
+        let orphan = child(&builder, &parent.hash(), "miner", now());
+
+        let mut chain = new_chain();
+        assert_eq!(chain.add_block(orphan.clone()), Ok(ChainUpdate::Orphaned(parent.hash())));
+        assert!(chain.is_missing_parent(&parent.hash()));
+
+        assert!(matches!(chain.add_block(parent.clone()), Ok(ChainUpdate::Extended(_))));
+        let connected = chain.connect_orphans(&parent.hash());
+        assert_eq!(connected, vec![(orphan.clone(), ChainUpdate::Extended(orphan.hash()))]);
+        assert!(!chain.is_missing_parent(&parent.hash()));
+        assert_eq!(chain.get_leaf().unwrap(), orphan.hash());
+    }
+
+    #[test]
+    fn a_full_orphan_pool_evicts_the_oldest_orphan() {
+        let mut chain = new_chain();
+        let genesis_hash = chain.get_genesis_hash();
+        let pool_size = ChainMetaData::default().get_orphan_pool_size().unwrap();
+        for index in 0..=pool_size {
+            let mut orphan = child(&chain, &genesis_hash, "miner", now());
+            orphan.set_prev_hash(format!("unknown-{}", index));
+            orphan.set_hash(orphan.calculate_hash());
+            chain.add_block(orphan).unwrap();
+        }
+
+        assert!(!chain.is_missing_parent("unknown-0"));
+        assert!(chain.is_missing_parent("unknown-1"));
+        assert!(chain.is_missing_parent(&format!("unknown-{}", pool_size)));
+        assert_eq!(chain.take_orphans("unknown-1").len(), 1);
+        assert!(!chain.is_missing_parent("unknown-1"));
+    }
+
+    #[test]
+    fn a_heavier_branch_takes_over_and_its_state_applies() {
+        let mut chain = new_chain();
+        let genesis_hash = chain.get_genesis_hash();
+        let a1 = extend(&mut chain, &genesis_hash, "alice", now());
+
+        let b1 = child(&chain, &genesis_hash, "bob", now());
+        assert!(matches!(chain.add_block(b1.clone()), Ok(ChainUpdate::SideBranch(_))));
+        assert_eq!(chain.get_leaf().unwrap(), a1);
+
+        let b2 = child(&chain, &b1.hash(), "bob", now());
+        let Ok(ChainUpdate::Reorganized(reorg)) = chain.add_block(b2.clone()) else {
+            panic!("the heavier branch did not take over");
+        };
+        assert_eq!(reorg.common_ancestor, genesis_hash);
+        let hashes = |blocks: &[Block]| blocks.iter().map(|block| block.hash()).collect::<Vec<_>>();
+        assert_eq!(hashes(&reorg.disconnected), vec![a1.clone()]);
+        assert_eq!(hashes(&reorg.connected), vec![b1.hash(), b2.hash()]);
+
+        assert_eq!(chain.get_leaf().unwrap(), b2.hash());
+        assert_eq!(chain.get_block_by_height(1).unwrap().hash(), b1.hash());
+        assert_eq!(chain.get_balance("alice").unwrap(), 0);
+        let rewards = block_subsidy(1).unwrap() + block_subsidy(2).unwrap();
+        assert_eq!(chain.get_balance("bob").unwrap(), rewards);
+    }
+
+    #[test]
+    fn finalized_blocks_are_never_reorganized_away() {
+        let mut chain = new_chain();
+        let genesis_hash = chain.get_genesis_hash();
+        let a1 = extend(&mut chain, &genesis_hash, "alice", now());
+        chain.finalize_block(&a1).unwrap();
+
+        assert!(chain.add_block(child(&chain, &genesis_hash, "bob", now())).is_err());
+        let a2 = extend(&mut chain, &a1, "alice", now());
+        assert_eq!(chain.get_leaf().unwrap(), a2);
     }
 }
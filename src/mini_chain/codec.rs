@@ -0,0 +1,223 @@
+// Deterministic binary encoding shared by hashing, persistence and network transport.
+// Integers are big-endian, `usize` is widened to `u64`, strings and vectors are prefixed
+// with a `u32` length and options with a one byte tag.
+pub const CODEC_VERSION: u8 = 1;
+
+pub trait Encode {
+    fn encode(&self, buf: &mut Vec<u8>);
+}
+
+pub trait Decode: Sized {
+    fn decode(reader: &mut Reader) -> Result<Self, String>;
+}
+
+// Encodes a value prefixed with the codec version.
+pub fn to_bytes<T: Encode>(value: &T) -> Vec<u8> {
+    let mut buf = vec![CODEC_VERSION];
+    value.encode(&mut buf);
+    buf
+}
+
+// Decodes a value produced by `to_bytes`, rejecting unknown versions and trailing bytes.
+pub fn from_bytes<T: Decode>(bytes: &[u8]) -> Result<T, String> {
+    let mut reader = Reader::new(bytes);
+    let version = u8::decode(&mut reader)?;
+    if version != CODEC_VERSION {
+        return Err(format!("Unsupported codec version: {}", version));
+    }
+
+    let value = T::decode(&mut reader)?;
+    if !reader.is_empty() {
+        return Err(format!("{} trailing bytes after decoding", reader.remaining()));
+    }
+    Ok(value)
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.remaining() < len {
+            return Err(format!(
+                "Unexpected end of input: needs {} bytes, {} left",
+                len,
+                self.remaining()
+            ));
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+}
+
+impl Encode for u8 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(*self);
+    }
+}
+
+impl Decode for u8 {
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        Ok(reader.read_bytes(1)?[0])
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.push(*self as u8);
+    }
+}
+
+impl Decode for bool {
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        match u8::decode(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(format!("Invalid bool tag: {}", tag)),
+        }
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_be_bytes());
+    }
+}
+
+impl Decode for u32 {
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        let bytes = reader.read_bytes(4)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+impl Encode for u64 {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_be_bytes());
+    }
+}
+
+impl Decode for u64 {
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        let bytes = reader.read_bytes(8)?;
+        Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+impl Encode for usize {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (*self as u64).encode(buf);
+    }
+}
+
+impl Decode for usize {
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        usize::try_from(u64::decode(reader)?).map_err(|e| e.to_string())
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.len() as u32).encode(buf);
+        buf.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        let len = u32::decode(reader)? as usize;
+        let bytes = reader.read_bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|e| e.to_string())
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            Some(value) => {
+                buf.push(1);
+                value.encode(buf);
+            }
+            None => buf.push(0),
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        match u8::decode(reader)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(reader)?)),
+            tag => Err(format!("Invalid option tag: {}", tag)),
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        (self.len() as u32).encode(buf);
+        for item in self {
+            item.encode(buf);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        let len = u32::decode(reader)? as usize;
+        // Every item takes at least one byte, so a longer length can only be corrupt input.
+        if len > reader.remaining() {
+            return Err(format!("Invalid vector length: {}", len));
+        }
+        (0..len).map(|_| T::decode(reader)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hashes are computed over this layout, so it must not change without bumping the version.
+    #[test]
+    fn layout_is_fixed() {
+        let mut buf = vec![];
+        Some("ab".to_string()).encode(&mut buf);
+        vec![7u32].encode(&mut buf);
+        true.encode(&mut buf);
+        usize::MAX.encode(&mut buf);
+
+        let mut expected = vec![1, 0, 0, 0, 2, b'a', b'b', 0, 0, 0, 1, 0, 0, 0, 7, 1];
+        expected.extend_from_slice(&[0xff; 8]);
+        assert_eq!(buf, expected);
+
+        let mut reader = Reader::new(&buf);
+        assert_eq!(Option::<String>::decode(&mut reader), Ok(Some("ab".to_string())));
+        assert_eq!(Vec::<u32>::decode(&mut reader), Ok(vec![7]));
+        assert_eq!(bool::decode(&mut reader), Ok(true));
+        assert_eq!(u64::decode(&mut reader), Ok(u64::MAX));
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn invalid_tags_and_lengths_are_rejected() {
+        assert!(from_bytes::<bool>(&[CODEC_VERSION, 2]).is_err());
+        assert!(from_bytes::<Option<u8>>(&[CODEC_VERSION, 2, 0]).is_err());
+        assert!(from_bytes::<Vec<u8>>(&[CODEC_VERSION, 0xff, 0xff, 0xff, 0xff, 0]).is_err());
+        assert!(from_bytes::<String>(&[CODEC_VERSION, 0, 0, 0, 1, 0xff]).is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use super::{
+    block::{Block, BlockConfigurer},
+    metadata::{ChainMetaData, ChainMetaDataOperation},
+    pow,
+    transaction::Transaction,
+};
+use primitive_types::U256;
+
+// Everything the first block of a chain is built from. Nodes sharing a config share the genesis block,
+// and with it the chain ID.
+#[derive(Debug, PartialEq, Clone)]
+pub struct GenesisConfig {
+    pub timestamp: usize,
+    pub difficulty: usize,
+    pub allocations: Vec<(String, usize)>,
+    // Addresses of the nodes whose votes finalize blocks.
+    pub validators: Vec<String>,
+}
+
+impl GenesisConfig {
+    pub fn new(allocations: Vec<(String, usize)>, validators: Vec<String>) -> Result<Self, String> {
+        let chain_metadata = ChainMetaData::default();
+        // Any difficulty past the limit would leave a zero target no hash can meet.
+        let difficulty = chain_metadata.get_block_difficulty()?;
+        if U256::from(difficulty) > pow::pow_limit() {
+            return Err(format!(
+                "Block difficulty {} is above the proof of work limit",
+                difficulty
+            ));
+        }
+        Ok(Self {
+            timestamp: chain_metadata.get_genesis_timestamp()?,
+            difficulty,
+            allocations,
+            validators,
+        })
+    }
+
+    // The genesis block has no parent, builder or proof of work. It holds one signerless transaction per
+    // allocation and the validator set in its header, so its hash commits to both. It also sets the
+    // difficulty of the first block.
+    pub fn genesis_block(&self) -> Block {
+        let mut block = Block::default();
+        block.set_timestamp(self.timestamp);
+        block.set_block_sequence(0);
+        block.set_validators(self.validators.clone());
+        for (index, (addr, amount)) in self.allocations.iter().cloned().enumerate() {
+            // The chain ID is derived from this block, so its transactions cannot carry it.
+            let mut tx = Transaction::new_coinbase(0, addr, amount, 0);
+            tx.timestamp = self.timestamp;
+            tx.nonce = index;
+            tx.hash = tx.calculate_hash();
+            block.add_transaction(tx);
+        }
+
+        let target = pow::target_from_difficulty(U256::from(self.difficulty));
+        block.set_bits(pow::bits_from_target(target));
+        block.set_hash(block.calculate_hash());
+        block
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        chain_id_from_hash(&self.genesis_block().hash())
+    }
+}
+
+// The first 8 bytes of the genesis hash.
+pub fn chain_id_from_hash(hash: &str) -> u64 {
+    hash.get(..16)
+        .and_then(|prefix| u64::from_str_radix(prefix, 16).ok())
+        .unwrap_or_default()
+}
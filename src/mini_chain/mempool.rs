@@ -1,41 +1,450 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
 
-use super::transaction::{Transaction, TxExisting, TxPoolRecord, TxStatus};
+use super::{
+    block::Block,
+    codec,
+    metadata::{ChainMetaData, ChainMetaDataOperation},
+    state::{Account, WorldState, WorldStateOperation},
+    transaction::{Transaction, TxExisting, TxPoolRecord, TxRejectReason, TxStatus},
+};
 
-#[derive(Debug, Clone)]
+struct MemPoolLimits {
+    max_count: usize,
+    max_bytes: usize,
+    expiry: Duration,
+    fee_bump: usize,
+    min_relay_fee: usize,
+}
+
+impl MemPoolLimits {
+    fn load() -> Result<Self, String> {
+        let chain_metadata = ChainMetaData::default();
+        Ok(Self {
+            max_count: chain_metadata.get_mempool_max_count()?,
+            max_bytes: chain_metadata.get_mempool_max_bytes()?,
+            expiry: Duration::from_millis(chain_metadata.get_mempool_tx_expiry()? as u64),
+            fee_bump: chain_metadata.get_replacement_fee_bump()?,
+            min_relay_fee: chain_metadata.get_min_relay_fee()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct MemPool {
     pub txpool: HashMap<String, TxPoolRecord>,
+    // Hashes of the pending transactions, keyed by signer and nonce.
+    pending: HashMap<String, BTreeMap<usize, String>>,
+    // Transactions whose nonce is ahead of the signer's next expected one, keyed by signer and nonce.
+    pub queued: HashMap<String, BTreeMap<usize, Transaction>>,
+    // Arrival time of every pending and queued transaction, keyed by hash.
+    received_at: HashMap<String, Instant>,
+    // Final status of transactions that left the pool, kept for lookups until they expire.
+    settled: HashMap<String, (TxStatus, Instant)>,
+    // Encoded size of the pending and queued transactions, kept up to date on every insert and removal.
+    bytes: usize,
 }
 
-impl Default for MemPool {
-    fn default() -> Self {
-        Self {
-            txpool: HashMap::new(),
+impl MemPool {
+    fn next_nonce(&self, signer: &str, account_nonce: usize) -> usize {
+        let mut next_nonce = account_nonce;
+        if let Some(nonces) = self.pending.get(signer) {
+            for nonce in nonces.range(account_nonce..).map(|(nonce, _)| *nonce) {
+                if nonce != next_nonce {
+                    break;
+                }
+                next_nonce += 1;
+            }
+        }
+        next_nonce
+    }
+
+    fn insert_pending(&mut self, tx: Transaction) {
+        self.pending
+            .entry(tx.signer.clone())
+            .or_default()
+            .insert(tx.nonce, tx.hash.clone());
+        self.txpool.insert(
+            tx.hash.clone(),
+            TxPoolRecord {
+                status: TxStatus::RECEIVED,
+                transaction: tx,
+            },
+        );
+    }
+
+    // Moves queued transactions of the signer into the pool as long as their nonces are contiguous.
+    fn promote_queued(&mut self, signer: &str, mut next_nonce: usize) {
+        let Some(queue) = self.queued.get_mut(signer) else {
+            return;
+        };
+
+        let mut promoted = vec![];
+        while let Some(tx) = queue.remove(&next_nonce) {
+            promoted.push(tx);
+            next_nonce += 1;
+        }
+        if queue.is_empty() {
+            self.queued.remove(signer);
+        }
+
+        for tx in promoted {
+            self.insert_pending(tx);
+        }
+    }
+
+    fn pool_transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.txpool
+            .values()
+            .map(|record| &record.transaction)
+            .chain(self.queued.values().flat_map(|queue| queue.values()))
+    }
+
+    fn pending_transaction(&self, hash: &str) -> Option<&Transaction> {
+        self.txpool.get(hash).map(|record| &record.transaction)
+    }
+
+    // The pending or queued transaction holding the signer's nonce.
+    fn find_by_nonce(&self, signer: &str, nonce: usize) -> Option<Transaction> {
+        if let Some(tx) = self.queued.get(signer).and_then(|queue| queue.get(&nonce)) {
+            return Some(tx.clone());
+        }
+        self.pending
+            .get(signer)
+            .and_then(|nonces| nonces.get(&nonce))
+            .and_then(|hash| self.pending_transaction(hash))
+            .cloned()
+    }
+
+    // The signer's pending and queued transactions with a nonce up to `nonce`.
+    fn find_up_to_nonce(&self, signer: &str, nonce: usize) -> Vec<Transaction> {
+        let pending = self
+            .pending
+            .get(signer)
+            .into_iter()
+            .flat_map(|nonces| nonces.range(..=nonce))
+            .filter_map(|(_, hash)| self.pending_transaction(hash));
+        let queued = self
+            .queued
+            .get(signer)
+            .into_iter()
+            .flat_map(|queue| queue.range(..=nonce))
+            .map(|(_, tx)| tx);
+        pending.chain(queued).cloned().collect()
+    }
+
+    fn remove_pending(&mut self, hash: &str) {
+        let Some(record) = self.txpool.remove(hash) else {
+            return;
+        };
+        let tx = record.transaction;
+        self.bytes -= tx.size();
+        if let Some(nonces) = self.pending.get_mut(&tx.signer) {
+            nonces.remove(&tx.nonce);
+            if nonces.is_empty() {
+                self.pending.remove(&tx.signer);
+            }
+        }
+    }
+
+    // Takes a single transaction out of the pool, leaving the signer's other nonces in place.
+    fn remove_transaction(&mut self, tx: &Transaction) {
+        self.remove_pending(&tx.hash);
+        if let Some(queue) = self.queued.get_mut(&tx.signer) {
+            if let Some(queued_tx) = queue.remove(&tx.nonce) {
+                self.bytes -= queued_tx.size();
+            }
+            if queue.is_empty() {
+                self.queued.remove(&tx.signer);
+            }
         }
+        self.received_at.remove(&tx.hash);
+    }
+
+    fn settle(&mut self, hash: &str, status: TxStatus) {
+        self.settled
+            .insert(hash.to_string(), (status, Instant::now()));
+    }
+
+    fn pool_len(&self) -> usize {
+        self.txpool.len() + self.queued.values().map(|queue| queue.len()).sum::<usize>()
+    }
+
+    fn pool_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // What the signer's pooled transactions spend in amounts and fees, leaving out the given nonce.
+    fn pending_spend(&self, signer: &str, except_nonce: usize) -> usize {
+        let pending = self
+            .pending
+            .get(signer)
+            .into_iter()
+            .flat_map(|nonces| nonces.values())
+            .filter_map(|hash| self.pending_transaction(hash));
+        let queued = self
+            .queued
+            .get(signer)
+            .into_iter()
+            .flat_map(|queue| queue.values());
+        pending
+            .chain(queued)
+            .filter(|tx| tx.nonce != except_nonce)
+            .map(|tx| tx.cost())
+            .fold(0, usize::saturating_add)
+    }
+
+    // Removes the signer's pending and queued transactions from `nonce` on.
+    fn remove_from_nonce(&mut self, signer: &str, nonce: usize, reason: TxRejectReason) {
+        let mut removed = vec![];
+        if let Some(nonces) = self.pending.get_mut(signer) {
+            removed.extend(nonces.split_off(&nonce).into_values());
+            if nonces.is_empty() {
+                self.pending.remove(signer);
+            }
+        }
+        for hash in removed.iter() {
+            if let Some(record) = self.txpool.remove(hash) {
+                self.bytes -= record.transaction.size();
+            }
+        }
+        if let Some(queue) = self.queued.get_mut(signer) {
+            for tx in queue.split_off(&nonce).into_values() {
+                self.bytes -= tx.size();
+                removed.push(tx.hash);
+            }
+            if queue.is_empty() {
+                self.queued.remove(signer);
+            }
+        }
+        for hash in removed {
+            self.received_at.remove(&hash);
+            self.settle(&hash, TxStatus::REJECTED(reason.clone()));
+        }
+    }
+
+    // Drops transactions older than `expiry` together with the signer's later ones, which could no longer apply.
+    fn expire_stale(&mut self, expiry: Duration) {
+        let expired: Vec<(String, usize)> = self
+            .pool_transactions()
+            .filter(|tx| {
+                self.received_at
+                    .get(&tx.hash)
+                    .is_some_and(|received_at| received_at.elapsed() >= expiry)
+            })
+            .map(|tx| (tx.signer.clone(), tx.nonce))
+            .collect();
+
+        for (signer, nonce) in expired {
+            self.remove_from_nonce(&signer, nonce, TxRejectReason::Expired);
+        }
+        self.settled
+            .retain(|_, (_, settled_at)| settled_at.elapsed() < expiry);
+    }
+
+    // The lowest paying transaction that can leave without opening a nonce gap: each signer's last one.
+    fn eviction_candidate(&self, except_signer: &str) -> Option<Transaction> {
+        let queued_last = self
+            .queued
+            .values()
+            .filter_map(|queue| queue.last_key_value().map(|(_, tx)| tx));
+        // Queued nonces are always ahead of pending ones, so only signers without a queue end in the pool.
+        let pending_last = self
+            .pending
+            .iter()
+            .filter(|(signer, _)| !self.queued.contains_key(*signer))
+            .filter_map(|(_, nonces)| nonces.last_key_value())
+            .filter_map(|(_, hash)| self.pending_transaction(hash));
+
+        queued_last
+            .chain(pending_last)
+            .filter(|tx| tx.signer != except_signer)
+            .min_by(|a, b| a.cmp_fee_rate(b).then_with(|| a.hash.cmp(&b.hash)))
+            .cloned()
+    }
+
+    // Minimum fee per 1000 bytes for a transaction to enter the pool. Once the pool is past half full
+    // by count or by bytes, it climbs linearly up to four times the configured minimum.
+    pub fn min_relay_fee(&self) -> Result<usize, String> {
+        let limits = MemPoolLimits::load()?;
+        let usage = (self.pool_len() * 1000 / limits.max_count.max(1))
+            .max(self.pool_bytes() * 1000 / limits.max_bytes.max(1))
+            .min(1000);
+        if usage <= 500 {
+            return Ok(limits.min_relay_fee);
+        }
+        Ok(limits.min_relay_fee * (1000 + (usage - 500) * 6) / 1000)
+    }
+
+    fn admit(
+        &mut self,
+        tx: Transaction,
+        account: &Account,
+        limits: &MemPoolLimits,
+    ) -> Result<(), (TxRejectReason, String)> {
+        if tx.is_coinbase() {
+            return Err((
+                TxRejectReason::Coinbase,
+                format!("Coinbase {} cannot be relayed", tx.hash),
+            ));
+        }
+        tx.verify_hash()
+            .map_err(|e| (TxRejectReason::InvalidHash, e))?;
+        tx.verify_signature()
+            .map_err(|e| (TxRejectReason::InvalidSignature, e))?;
+
+        if tx.nonce < account.nonce {
+            return Err((
+                TxRejectReason::StaleNonce,
+                format!(
+                    "Stale nonce: {} is already at {}, got {}",
+                    tx.signer, account.nonce, tx.nonce
+                ),
+            ));
+        }
+
+        // Every pooled transaction of the signer must stay affordable together, whatever order they apply in.
+        let spend = self
+            .pending_spend(&tx.signer, tx.nonce)
+            .saturating_add(tx.cost());
+        if spend > account.balance {
+            return Err((
+                TxRejectReason::InsufficientFunds,
+                format!(
+                    "Insufficient funds: {} holds {}, its pooled transactions with {} spend {}",
+                    tx.signer, account.balance, tx.hash, spend
+                ),
+            ));
+        }
+
+        // A transaction for a nonce already in the pool replaces the old one if it pays
+        // `replacement_fee_bump` percent more, and at least one more.
+        let replaced = self.find_by_nonce(&tx.signer, tx.nonce);
+        if let Some(old_tx) = &replaced {
+            // Swapping out a transaction a template already holds would leave the template invalid.
+            if self
+                .txpool
+                .get(&old_tx.hash)
+                .is_some_and(|record| record.status == TxStatus::PROCESSING)
+            {
+                return Err((
+                    TxRejectReason::Processing,
+                    format!(
+                        "Replacement refused: nonce {} of {} is already in a block template",
+                        tx.nonce, tx.signer
+                    ),
+                ));
+            }
+            let required_fee = old_tx
+                .fee
+                .saturating_add((old_tx.fee.saturating_mul(limits.fee_bump) / 100).max(1));
+            if tx.fee < required_fee {
+                return Err((
+                    TxRejectReason::Underpriced,
+                    format!(
+                        "Replacement underpriced: nonce {} of {} needs a fee of {}, got {}",
+                        tx.nonce, tx.signer, required_fee, tx.fee
+                    ),
+                ));
+            }
+        }
+
+        let size = tx.size();
+        let min_relay_fee = self.min_relay_fee().unwrap_or(limits.min_relay_fee);
+        if tx.fee.saturating_mul(1000) < min_relay_fee * size {
+            return Err((
+                TxRejectReason::FeeTooLow,
+                format!(
+                    "Fee too low: {} pays {} for {} bytes, the pool asks {} per 1000 bytes",
+                    tx.hash, tx.fee, size, min_relay_fee
+                ),
+            ));
+        }
+
+        // Make room by evicting lower paying transactions, or turn this one away if it pays the least.
+        let mut count = self.pool_len() - replaced.iter().count();
+        let mut bytes = self.pool_bytes() - replaced.as_ref().map_or(0, |old_tx| old_tx.size());
+        while count + 1 > limits.max_count || bytes + size > limits.max_bytes {
+            let Some(evicted) = self
+                .eviction_candidate(&tx.signer)
+                .filter(|evicted| evicted.cmp_fee_rate(&tx).is_lt())
+            else {
+                return Err((
+                    TxRejectReason::PoolFull,
+                    format!("Mempool full: {} does not outbid any transaction", tx.hash),
+                ));
+            };
+            count -= 1;
+            bytes -= evicted.size();
+            self.remove_from_nonce(&evicted.signer, evicted.nonce, TxRejectReason::Evicted);
+        }
+
+        if let Some(old_tx) = replaced {
+            self.remove_transaction(&old_tx);
+            self.settle(&old_tx.hash, TxStatus::REJECTED(TxRejectReason::Replaced));
+        }
+
+        self.received_at.insert(tx.hash.clone(), Instant::now());
+        self.settled.remove(&tx.hash);
+        self.bytes += size;
+        let next_nonce = self.next_nonce(&tx.signer, account.nonce);
+        if tx.nonce > next_nonce {
+            self.queued
+                .entry(tx.signer.clone())
+                .or_default()
+                .insert(tx.nonce, tx);
+            return Ok(());
+        }
+
+        let signer = tx.signer.clone();
+        self.insert_pending(tx);
+        self.promote_queued(&signer, next_nonce + 1);
+        Ok(())
     }
 }
 
 #[async_trait]
 pub trait MemPoolOperation {
-    async fn add_transaction(&mut self, tx: Transaction) -> Result<(), String>;
+    async fn add_transaction(&mut self, tx: Transaction, account: &Account) -> Result<(), String>;
     async fn existing_transaction(&self, tx: Transaction) -> TxExisting;
+    async fn get_transaction_status(&self, hash: &str) -> Option<TxStatus>;
+    async fn get_next_nonce(&self, signer: &str, account_nonce: usize) -> usize;
     async fn pickup_transaction(&mut self, count: usize) -> Result<Vec<Transaction>, String>;
+    async fn release_transactions(&mut self, hashes: Vec<String>) -> Result<(), String>;
+    async fn release_all_transactions(&mut self) -> Result<(), String>;
+    async fn approve_transactions(&mut self, block: &Block, height: u64) -> Result<(), String>;
+    async fn reopen_transactions(&mut self, hashes: Vec<String>) -> Result<(), String>;
     async fn remove_transactions(&mut self, hashes: Vec<String>) -> Result<(), String>;
+    async fn dump_transactions(&self) -> Vec<u8>;
+    async fn restore_transactions(
+        &mut self,
+        bytes: &[u8],
+        state: &WorldState,
+        chain_id: u64,
+    ) -> Result<usize, String>;
 }
 
 #[async_trait]
 impl MemPoolOperation for MemPool {
-    async fn add_transaction(&mut self, tx: Transaction) -> Result<(), String> {
-        self.txpool.insert(
-            tx.hash.clone(),
-            TxPoolRecord {
-                status: TxStatus::RECEIVED,
-                transaction: tx.clone(),
-            },
-        );
-        Ok(())
+    // Rejected transactions are remembered with the reason. Ones pending or approved are not taken again.
+    async fn add_transaction(&mut self, tx: Transaction, account: &Account) -> Result<(), String> {
+        let limits = MemPoolLimits::load()?;
+        self.expire_stale(limits.expiry);
+
+        let hash = tx.hash.clone();
+        let status = self.get_transaction_status(&hash).await;
+        if status.is_some() && !matches!(status, Some(TxStatus::REJECTED(_))) {
+            return Err(format!("Transaction {} is already known", hash));
+        }
+
+        self.admit(tx, account, &limits).map_err(|(reason, e)| {
+            self.settle(&hash, TxStatus::REJECTED(reason));
+            e
+        })
     }
 
     async fn existing_transaction(&self, tx: Transaction) -> TxExisting {
@@ -45,23 +454,426 @@ impl MemPoolOperation for MemPool {
         }
     }
 
+    async fn get_transaction_status(&self, hash: &str) -> Option<TxStatus> {
+        if let Some(record) = self.txpool.get(hash) {
+            return Some(record.status.clone());
+        }
+        if self.received_at.contains_key(hash) {
+            return Some(TxStatus::RECEIVED);
+        }
+        self.settled.get(hash).map(|(status, _)| status.clone())
+    }
+
+    // The nonce following the signer's pending transactions, which run contiguously from the account nonce.
+    async fn get_next_nonce(&self, signer: &str, account_nonce: usize) -> usize {
+        self.next_nonce(signer, account_nonce)
+    }
+
+    // Picks the best paying transactions by fee rate and marks them PROCESSING so later templates
+    // leave them out. A signer's transaction only competes once the one before it in nonce order
+    // has been picked, so the selection applies sequentially.
     async fn pickup_transaction(&mut self, count: usize) -> Result<Vec<Transaction>, String> {
-        let pool_received_records: Vec<Transaction> = self
+        let limits = MemPoolLimits::load()?;
+        self.expire_stale(limits.expiry);
+
+        let mut pool_received_records: Vec<Transaction> = self
             .txpool
-            .iter()
-            .filter(|(_key, txrecord)| txrecord.status == TxStatus::RECEIVED)
-            .take(count)
-            .map(|(_key, record)| record.transaction.clone())
+            .values()
+            .filter(|txrecord| txrecord.status == TxStatus::RECEIVED)
+            .map(|record| record.transaction.clone())
             .collect();
+        pool_received_records.sort_by_key(|tx| tx.nonce);
+
+        let mut signer_queues: HashMap<String, VecDeque<Transaction>> = HashMap::new();
+        for tx in pool_received_records {
+            signer_queues.entry(tx.signer.clone()).or_default().push_back(tx);
+        }
+
+        let mut picked = vec![];
+        while picked.len() < count {
+            let best_signer = signer_queues
+                .iter()
+                .filter_map(|(signer, queue)| queue.front().map(|tx| (signer, tx)))
+                .max_by(|(_, a), (_, b)| a.cmp_fee_rate(b).then_with(|| b.hash.cmp(&a.hash)))
+                .map(|(signer, _)| signer.clone());
+            let Some(signer) = best_signer else {
+                break;
+            };
+
+            let queue = signer_queues.get_mut(&signer).unwrap();
+            picked.extend(queue.pop_front());
+            if queue.is_empty() {
+                signer_queues.remove(&signer);
+            }
+        }
+
+        for tx in picked.iter() {
+            if let Some(record) = self.txpool.get_mut(&tx.hash) {
+                record.status = TxStatus::PROCESSING;
+            }
+        }
+        Ok(picked)
+    }
+
+    // Makes picked transactions available to block templates again.
+    async fn release_transactions(&mut self, hashes: Vec<String>) -> Result<(), String> {
+        for hash in hashes {
+            if let Some(record) = self.txpool.get_mut(&hash) {
+                record.status = TxStatus::RECEIVED;
+            }
+        }
+        Ok(())
+    }
+
+    async fn release_all_transactions(&mut self) -> Result<(), String> {
+        for record in self.txpool.values_mut() {
+            record.status = TxStatus::RECEIVED;
+        }
+        Ok(())
+    }
+
+    // Settles the transactions of a block joining the canonical chain. Pool transactions of the same
+    // signers with nonces the block used up can never apply anymore and are rejected.
+    async fn approve_transactions(&mut self, block: &Block, height: u64) -> Result<(), String> {
+        for tx in block.transactions().iter().filter(|tx| !tx.is_coinbase()) {
+            self.remove_transaction(tx);
+            self.settle(
+                &tx.hash,
+                TxStatus::APPROVED {
+                    block_hash: block.hash(),
+                    height,
+                },
+            );
 
-        Ok(pool_received_records)
+            for conflict in self.find_up_to_nonce(&tx.signer, tx.nonce) {
+                self.remove_transaction(&conflict);
+                self.settle(&conflict.hash, TxStatus::REJECTED(TxRejectReason::Conflicted));
+            }
+        }
+        Ok(())
+    }
+
+    // Forgets the approval of transactions whose block left the canonical chain.
+    async fn reopen_transactions(&mut self, hashes: Vec<String>) -> Result<(), String> {
+        for hash in hashes {
+            if matches!(self.settled.get(&hash), Some((TxStatus::APPROVED { .. }, _))) {
+                self.settled.remove(&hash);
+            }
+        }
+        Ok(())
     }
 
     async fn remove_transactions(&mut self, hashes: Vec<String>) -> Result<(), String> {
         for hash in hashes {
-            self.txpool.remove(&hash);
+            self.remove_pending(&hash);
+            self.received_at.remove(&hash);
         }
 
         Ok(())
     }
+    // Encodes the pending and queued transactions, each signer's in nonce order.
+    async fn dump_transactions(&self) -> Vec<u8> {
+        let mut transactions: Vec<Transaction> = self.pool_transactions().cloned().collect();
+        transactions.sort_by(|a, b| (&a.signer, a.nonce).cmp(&(&b.signer, b.nonce)));
+        codec::to_bytes(&transactions)
+    }
+
+    // Re-admits dumped transactions that still apply on top of the given state. Nonces already used
+    // are dropped. Once a signer's transaction is refused by the admission rules, unaffordable ones
+    // included, the signer's later ones are dropped too, as they could never apply. Returns the number
+    // re-admitted.
+    async fn restore_transactions(
+        &mut self,
+        bytes: &[u8],
+        state: &WorldState,
+        chain_id: u64,
+    ) -> Result<usize, String> {
+        let transactions = codec::from_bytes::<Vec<Transaction>>(bytes)?;
+
+        let mut dropped_signers: HashSet<String> = HashSet::new();
+        let mut restored = 0;
+        for tx in transactions {
+            let account = state.get_account(&tx.signer);
+            if tx.chain_id != chain_id
+                || tx.nonce < account.nonce
+                || dropped_signers.contains(&tx.signer)
+            {
+                continue;
+            }
+            let signer = tx.signer.clone();
+            if self.add_transaction(tx, &account).await.is_err() {
+                dropped_signers.insert(signer);
+                continue;
+            }
+            restored += 1;
+        }
+        Ok(restored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mini_chain::{
+        address::{verify_signature, Address},
+        block::BlockConfigurer,
+    };
+
+    fn transfer(from: &Address, fee: usize, nonce: usize) -> Transaction {
+        let mut tx = Transaction::new(0, "payee".to_string(), 1, fee, nonce);
+        tx.sign_transaction(from);
+        tx
+    }
+
+    fn account(nonce: usize) -> Account {
+        Account {
+            balance: 1_000,
+            nonce,
+        }
+    }
+
+    fn limits(max_count: usize) -> MemPoolLimits {
+        MemPoolLimits {
+            max_count,
+            max_bytes: usize::MAX,
+            expiry: Duration::from_secs(600),
+            fee_bump: 10,
+            min_relay_fee: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn transactions_wait_for_the_nonces_before_them() {
+        let signer = Address::new();
+        let mut mempool = MemPool::default();
+        for nonce in [2, 1] {
+            mempool.add_transaction(transfer(&signer, 5, nonce), &account(0)).await.unwrap();
+        }
+        assert!(mempool.txpool.is_empty());
+        assert_eq!(mempool.pickup_transaction(10).await.unwrap(), vec![]);
+
+        mempool.add_transaction(transfer(&signer, 1, 0), &account(0)).await.unwrap();
+        assert!(mempool.queued.is_empty());
+        let nonces: Vec<usize> = mempool
+            .pickup_transaction(10)
+            .await
+            .unwrap()
+            .iter()
+            .map(|tx| tx.nonce)
+            .collect();
+        assert_eq!(nonces, vec![0, 1, 2]);
+
+        let stale = transfer(&signer, 5, 0);
+        assert!(mempool.add_transaction(stale.clone(), &account(1)).await.is_err());
+        assert_eq!(
+            mempool.get_transaction_status(&stale.hash).await,
+            Some(TxStatus::REJECTED(TxRejectReason::StaleNonce))
+        );
+    }
+
+    #[tokio::test]
+    async fn forged_signatures_are_refused() {
+        let signer = Address::new();
+        let tx = transfer(&signer, 5, 0);
+        assert!(verify_signature(&tx.signer, &tx.signing_bytes(), &tx.signature).is_ok());
+
+        let mut mutated = tx.clone();
+        let mut signature = hex::decode(&mutated.signature).unwrap();
+        signature[0] ^= 1;
+        mutated.signature = hex::encode(signature);
+        let mut altered = tx.clone();
+        altered.payload.amount += 1;
+        // Signed with another key while claiming the signer's address.
+        let mut forged = transfer(&Address::new(), 5, 0);
+        forged.signer = tx.signer.clone();
+
+        let mut mempool = MemPool::default();
+        for mut bad in [mutated, altered, forged] {
+            assert!(verify_signature(&bad.signer, &bad.signing_bytes(), &bad.signature).is_err());
+            // A matching hash leaves the signature as the only thing wrong.
+            bad.hash = bad.calculate_hash();
+            assert!(mempool.add_transaction(bad.clone(), &account(0)).await.is_err());
+            assert_eq!(
+                mempool.get_transaction_status(&bad.hash).await,
+                Some(TxStatus::REJECTED(TxRejectReason::InvalidSignature))
+            );
+        }
+        assert_eq!(mempool.pool_len(), 0);
+    }
+
+    // Every pending transaction is indexed under its signer and nonce, and nothing else is. The byte
+    // total matches the pooled transactions.
+    fn assert_indexed(mempool: &MemPool) {
+        let bytes: usize = mempool.pool_transactions().map(|tx| tx.size()).sum();
+        assert_eq!(mempool.pool_bytes(), bytes);
+        let indexed: usize = mempool.pending.values().map(|nonces| nonces.len()).sum();
+        assert_eq!(indexed, mempool.txpool.len());
+        for record in mempool.txpool.values() {
+            let tx = &record.transaction;
+            assert_eq!(mempool.pending[&tx.signer].get(&tx.nonce), Some(&tx.hash));
+        }
+    }
+
+    #[tokio::test]
+    async fn committed_nonces_clear_the_pool_index() {
+        let (signer, other) = (Address::new(), Address::new());
+        let mut mempool = MemPool::default();
+        for nonce in [0, 1, 2, 4] {
+            mempool.add_transaction(transfer(&signer, 5, nonce), &account(0)).await.unwrap();
+        }
+        mempool.add_transaction(transfer(&other, 5, 0), &account(0)).await.unwrap();
+        mempool.add_transaction(transfer(&signer, 6, 1), &account(0)).await.unwrap();
+        assert_indexed(&mempool);
+
+        // A block spending nonces 0 and 1 with other transactions conflicts with the pooled ones.
+        let mut block = Block::default();
+        block.add_transaction(transfer(&signer, 1, 0));
+        block.add_transaction(transfer(&signer, 1, 1));
+        mempool.approve_transactions(&block, 1).await.unwrap();
+        assert_indexed(&mempool);
+        assert_eq!(mempool.next_nonce(signer.get_public_address(), 2), 3);
+        assert_eq!(mempool.pool_len(), 3);
+
+        let rest: Vec<String> = mempool.txpool.keys().cloned().collect();
+        mempool.remove_transactions(rest).await.unwrap();
+        assert_indexed(&mempool);
+        assert!(mempool.pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pooled_spends_must_stay_within_the_balance() {
+        let signer = Address::new();
+        let mut mempool = MemPool::default();
+        let budget = Account {
+            balance: 10,
+            nonce: 0,
+        };
+        // Each transfer spends an amount of 1 and a fee of 3.
+        mempool.add_transaction(transfer(&signer, 3, 0), &budget).await.unwrap();
+        mempool.add_transaction(transfer(&signer, 3, 2), &budget).await.unwrap();
+
+        let over_budget = transfer(&signer, 3, 1);
+        assert!(mempool.add_transaction(over_budget.clone(), &budget).await.is_err());
+        assert_eq!(
+            mempool.get_transaction_status(&over_budget.hash).await,
+            Some(TxStatus::REJECTED(TxRejectReason::InsufficientFunds))
+        );
+
+        // A replacement only counts once: its own fee, not the one it replaces.
+        mempool.add_transaction(transfer(&signer, 5, 2), &budget).await.unwrap();
+        assert!(mempool.add_transaction(transfer(&signer, 6, 2), &budget).await.is_err());
+        assert_indexed(&mempool);
+    }
+
+    #[tokio::test]
+    async fn pickup_orders_by_fee_rate_within_nonce_order() {
+        let (a, b, c) = (Address::new(), Address::new(), Address::new());
+        let mut mempool = MemPool::default();
+        let txs = [transfer(&a, 2, 0), transfer(&a, 9, 1), transfer(&b, 5, 0), transfer(&c, 3, 0)];
+        for tx in txs.iter() {
+            mempool.add_transaction(tx.clone(), &account(0)).await.unwrap();
+        }
+
+        // The best paying transaction waits behind its signer's cheaper first nonce.
+        let picked = mempool.pickup_transaction(10).await.unwrap();
+        let expected: Vec<String> = [2, 3, 0, 1].iter().map(|&i| txs[i].hash.clone()).collect();
+        assert_eq!(picked.iter().map(|tx| tx.hash.clone()).collect::<Vec<_>>(), expected);
+    }
+
+    #[tokio::test]
+    async fn replacements_must_raise_the_fee() {
+        let signer = Address::new();
+        let mut mempool = MemPool::default();
+        let original = transfer(&signer, 10, 0);
+        mempool.add_transaction(original.clone(), &account(0)).await.unwrap();
+
+        let underpriced = transfer(&signer, 10, 0);
+        assert!(mempool.add_transaction(underpriced, &account(0)).await.is_err());
+
+        let replacement = transfer(&signer, 11, 0);
+        mempool.add_transaction(replacement.clone(), &account(0)).await.unwrap();
+        assert_eq!(
+            mempool.get_transaction_status(&original.hash).await,
+            Some(TxStatus::REJECTED(TxRejectReason::Replaced))
+        );
+        assert_eq!(
+            mempool.get_transaction_status(&replacement.hash).await,
+            Some(TxStatus::RECEIVED)
+        );
+    }
+
+    #[tokio::test]
+    async fn transactions_in_a_template_cannot_be_replaced() {
+        let signer = Address::new();
+        let mut mempool = MemPool::default();
+        let original = transfer(&signer, 10, 0);
+        mempool.add_transaction(original.clone(), &account(0)).await.unwrap();
+        mempool.pickup_transaction(10).await.unwrap();
+
+        let replacement = transfer(&signer, 20, 0);
+        assert!(mempool.add_transaction(replacement.clone(), &account(0)).await.is_err());
+        assert_eq!(
+            mempool.get_transaction_status(&replacement.hash).await,
+            Some(TxStatus::REJECTED(TxRejectReason::Processing))
+        );
+        assert_eq!(
+            mempool.get_transaction_status(&original.hash).await,
+            Some(TxStatus::PROCESSING)
+        );
+
+        // Once the template is dropped, the replacement goes through.
+        mempool.release_transactions(vec![original.hash.clone()]).await.unwrap();
+        mempool.add_transaction(replacement.clone(), &account(0)).await.unwrap();
+        assert_eq!(
+            mempool.get_transaction_status(&original.hash).await,
+            Some(TxStatus::REJECTED(TxRejectReason::Replaced))
+        );
+    }
+
+    #[tokio::test]
+    async fn a_dumped_pool_restores_as_it_was() {
+        let (a, b) = (Address::new(), Address::new());
+        let mut mempool = MemPool::default();
+        for tx in [transfer(&a, 5, 0), transfer(&a, 5, 1), transfer(&a, 5, 3), transfer(&b, 7, 0)] {
+            mempool.add_transaction(tx, &account(0)).await.unwrap();
+        }
+        let bytes = mempool.dump_transactions().await;
+
+        let state = WorldState::new(vec![
+            (a.get_public_address().to_string(), 1_000),
+            (b.get_public_address().to_string(), 1_000),
+        ]);
+        let mut restored = MemPool::default();
+        assert_eq!(restored.restore_transactions(&bytes, &state, 0).await.unwrap(), 4);
+        assert_eq!(restored.dump_transactions().await, bytes);
+        assert_eq!(restored.txpool.len(), 3);
+        assert_eq!(restored.queued[a.get_public_address()].len(), 1);
+        assert_indexed(&restored);
+    }
+
+    #[test]
+    fn a_full_pool_evicts_the_lowest_paying_last_nonce() {
+        let (rich, poor, newcomer) = (Address::new(), Address::new(), Address::new());
+        let mut mempool = MemPool::default();
+        let limits = limits(3);
+        mempool.admit(transfer(&rich, 9, 0), &account(0), &limits).unwrap();
+        mempool.admit(transfer(&poor, 2, 0), &account(0), &limits).unwrap();
+        let poor_next = transfer(&poor, 3, 1);
+        mempool.admit(poor_next.clone(), &account(0), &limits).unwrap();
+
+        // The poor signer's last nonce goes, not its cheaper first one, which would open a gap.
+        mempool.admit(transfer(&newcomer, 5, 0), &account(0), &limits).unwrap();
+        assert_eq!(mempool.pool_len(), 3);
+        assert!(!mempool.txpool.contains_key(&poor_next.hash));
+        assert!(matches!(
+            mempool.settled.get(&poor_next.hash),
+            Some((TxStatus::REJECTED(TxRejectReason::Evicted), _))
+        ));
+
+        let cheap = transfer(&Address::new(), 1, 0);
+        assert!(matches!(
+            mempool.admit(cheap, &account(0), &limits),
+            Err((TxRejectReason::PoolFull, _))
+        ));
+    }
 }
@@ -0,0 +1,151 @@
+use sha3::{Digest, Sha3_256};
+
+// Leaves and inner nodes are hashed with different prefixes so a proof for one can never pass as the other.
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MerkleProofStep {
+    pub hash: String,
+    pub is_left: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MerkleProof {
+    pub tx_hash: String,
+    pub index: usize,
+    pub path: Vec<MerkleProofStep>,
+}
+
+fn hash_leaf(tx_hash: &str) -> String {
+    let mut hasher = Sha3_256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(tx_hash.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn hash_node(left: &str, right: &str) -> String {
+    let mut hasher = Sha3_256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left.as_bytes());
+    hasher.update(right.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+// Builds the next tree level. An odd node out is carried up unchanged instead of being duplicated.
+fn next_level(level: &[String]) -> Vec<String> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_node(left, right),
+            [single] => single.clone(),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+pub fn merkle_root(tx_hashes: &[String]) -> String {
+    if tx_hashes.is_empty() {
+        return format!("{:x}", Sha3_256::digest([]));
+    }
+
+    let mut level: Vec<String> = tx_hashes.iter().map(|hash| hash_leaf(hash)).collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level.remove(0)
+}
+
+pub fn merkle_proof(tx_hashes: &[String], tx_hash: &str) -> Option<MerkleProof> {
+    let index = tx_hashes.iter().position(|hash| hash == tx_hash)?;
+
+    let mut path = vec![];
+    let mut level: Vec<String> = tx_hashes.iter().map(|hash| hash_leaf(hash)).collect();
+    let mut pos = index;
+    while level.len() > 1 {
+        let sibling = pos ^ 1;
+        if sibling < level.len() {
+            path.push(MerkleProofStep {
+                hash: level[sibling].clone(),
+                is_left: sibling < pos,
+            });
+        }
+        level = next_level(&level);
+        pos /= 2;
+    }
+
+    Some(MerkleProof {
+        tx_hash: tx_hash.to_string(),
+        index,
+        path,
+    })
+}
+
+pub fn verify_merkle_proof(merkle_root: &str, proof: &MerkleProof) -> bool {
+    let computed = proof
+        .path
+        .iter()
+        .fold(hash_leaf(&proof.tx_hash), |acc, step| {
+            if step.is_left {
+                hash_node(&step.hash, &acc)
+            } else {
+                hash_node(&acc, &step.hash)
+            }
+        });
+    computed == merkle_root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx_hashes(count: usize) -> Vec<String> {
+        (0..count).map(|index| format!("tx{}", index)).collect()
+    }
+
+    #[test]
+    fn every_transaction_proves_against_the_root() {
+        for count in 1..=9 {
+            let hashes = tx_hashes(count);
+            let root = merkle_root(&hashes);
+            for hash in &hashes {
+                let proof = merkle_proof(&hashes, hash).unwrap();
+                assert!(verify_merkle_proof(&root, &proof), "{} of {}", hash, count);
+            }
+        }
+    }
+
+    #[test]
+    fn tampered_proofs_are_refused() {
+        let hashes = tx_hashes(5);
+        let root = merkle_root(&hashes);
+        let proof = merkle_proof(&hashes, "tx2").unwrap();
+
+        let mut other_tx = proof.clone();
+        other_tx.tx_hash = "tx9".to_string();
+        assert!(!verify_merkle_proof(&root, &other_tx));
+
+        let mut flipped = proof.clone();
+        flipped.path[0].is_left = !flipped.path[0].is_left;
+        assert!(!verify_merkle_proof(&root, &flipped));
+
+        assert!(!verify_merkle_proof(&merkle_root(&tx_hashes(4)), &proof));
+        assert!(merkle_proof(&hashes, "tx9").is_none());
+    }
+
+    #[test]
+    fn an_inner_node_cannot_pass_as_a_leaf() {
+        let hashes = tx_hashes(4);
+        let root = merkle_root(&hashes);
+        let inner = hash_node(&hash_leaf("tx0"), &hash_leaf("tx1"));
+        let proof = MerkleProof {
+            tx_hash: inner,
+            index: 0,
+            path: vec![MerkleProofStep {
+                hash: hash_node(&hash_leaf("tx2"), &hash_leaf("tx3")),
+                is_left: false,
+            }],
+        };
+        assert!(!verify_merkle_proof(&root, &proof));
+    }
+}
@@ -7,6 +7,25 @@ pub struct ChainMetaData {
     block_tx_pickup_period: usize,
     block_size: usize,
     block_difficulty: usize,
+    block_target_interval: usize,
+    difficulty_retarget_window: usize,
+    initial_balance: usize,
+    orphan_pool_size: usize,
+    orphan_block_expiry: usize,
+    miner_threads: usize,
+    block_subsidy: usize,
+    subsidy_halving_interval: usize,
+    mempool_max_count: usize,
+    mempool_max_bytes: usize,
+    mempool_tx_expiry: usize,
+    min_relay_fee: usize,
+    replacement_fee_bump: usize,
+    block_store_dir: String,
+    persist_mempool: bool,
+    genesis_timestamp: usize,
+    median_time_span: usize,
+    max_future_block_time: usize,
+    max_block_templates: usize,
 }
 
 impl Default for ChainMetaData {
@@ -19,7 +38,26 @@ impl Default for ChainMetaData {
             block_gen_period: 500,
             block_tx_pickup_period: 400,
             block_size: 20,
-            block_difficulty: 2,
+            block_difficulty: 128,
+            block_target_interval: 2500,
+            difficulty_retarget_window: 10,
+            initial_balance: 1000,
+            orphan_pool_size: 100,
+            orphan_block_expiry: 60000,
+            miner_threads: 2,
+            block_subsidy: 50,
+            subsidy_halving_interval: 100,
+            mempool_max_count: 5000,
+            mempool_max_bytes: 1000000,
+            mempool_tx_expiry: 600000,
+            min_relay_fee: 1,
+            replacement_fee_bump: 10,
+            block_store_dir: "data".to_string(),
+            persist_mempool: true,
+            genesis_timestamp: 1712000000,
+            median_time_span: 11,
+            max_future_block_time: 60000,
+            max_block_templates: 16,
         }
     }
 }
@@ -33,6 +71,25 @@ pub trait ChainMetaDataOperation {
     fn get_block_tx_pickup_period(&self) -> Result<usize, String>;
     fn get_block_size(&self) -> Result<usize, String>;
     fn get_block_difficulty(&self) -> Result<usize, String>;
+    fn get_block_target_interval(&self) -> Result<usize, String>;
+    fn get_difficulty_retarget_window(&self) -> Result<usize, String>;
+    fn get_initial_balance(&self) -> Result<usize, String>;
+    fn get_orphan_pool_size(&self) -> Result<usize, String>;
+    fn get_orphan_block_expiry(&self) -> Result<usize, String>;
+    fn get_miner_threads(&self) -> Result<usize, String>;
+    fn get_block_subsidy(&self) -> Result<usize, String>;
+    fn get_subsidy_halving_interval(&self) -> Result<usize, String>;
+    fn get_mempool_max_count(&self) -> Result<usize, String>;
+    fn get_mempool_max_bytes(&self) -> Result<usize, String>;
+    fn get_mempool_tx_expiry(&self) -> Result<usize, String>;
+    fn get_min_relay_fee(&self) -> Result<usize, String>;
+    fn get_replacement_fee_bump(&self) -> Result<usize, String>;
+    fn get_block_store_dir(&self) -> Result<String, String>;
+    fn get_persist_mempool(&self) -> Result<bool, String>;
+    fn get_genesis_timestamp(&self) -> Result<usize, String>;
+    fn get_median_time_span(&self) -> Result<usize, String>;
+    fn get_max_future_block_time(&self) -> Result<usize, String>;
+    fn get_max_block_templates(&self) -> Result<usize, String>;
 }
 
 impl ChainMetaDataOperation for ChainMetaData {
@@ -67,4 +124,80 @@ impl ChainMetaDataOperation for ChainMetaData {
     fn get_block_difficulty(&self) -> Result<usize, String> {
         Ok(self.block_difficulty)
     }
+
+    fn get_block_target_interval(&self) -> Result<usize, String> {
+        Ok(self.block_target_interval)
+    }
+
+    fn get_difficulty_retarget_window(&self) -> Result<usize, String> {
+        Ok(self.difficulty_retarget_window)
+    }
+
+    fn get_initial_balance(&self) -> Result<usize, String> {
+        Ok(self.initial_balance)
+    }
+
+    fn get_orphan_pool_size(&self) -> Result<usize, String> {
+        Ok(self.orphan_pool_size)
+    }
+
+    fn get_orphan_block_expiry(&self) -> Result<usize, String> {
+        Ok(self.orphan_block_expiry)
+    }
+
+    fn get_miner_threads(&self) -> Result<usize, String> {
+        Ok(self.miner_threads)
+    }
+
+    fn get_block_subsidy(&self) -> Result<usize, String> {
+        Ok(self.block_subsidy)
+    }
+
+    fn get_subsidy_halving_interval(&self) -> Result<usize, String> {
+        Ok(self.subsidy_halving_interval)
+    }
+
+    fn get_mempool_max_count(&self) -> Result<usize, String> {
+        Ok(self.mempool_max_count)
+    }
+
+    fn get_mempool_max_bytes(&self) -> Result<usize, String> {
+        Ok(self.mempool_max_bytes)
+    }
+
+    fn get_mempool_tx_expiry(&self) -> Result<usize, String> {
+        Ok(self.mempool_tx_expiry)
+    }
+
+    fn get_min_relay_fee(&self) -> Result<usize, String> {
+        Ok(self.min_relay_fee)
+    }
+
+    fn get_replacement_fee_bump(&self) -> Result<usize, String> {
+        Ok(self.replacement_fee_bump)
+    }
+
+    fn get_block_store_dir(&self) -> Result<String, String> {
+        Ok(self.block_store_dir.clone())
+    }
+
+    fn get_persist_mempool(&self) -> Result<bool, String> {
+        Ok(self.persist_mempool)
+    }
+
+    fn get_genesis_timestamp(&self) -> Result<usize, String> {
+        Ok(self.genesis_timestamp)
+    }
+
+    fn get_median_time_span(&self) -> Result<usize, String> {
+        Ok(self.median_time_span)
+    }
+
+    fn get_max_future_block_time(&self) -> Result<usize, String> {
+        Ok(self.max_future_block_time)
+    }
+
+    fn get_max_block_templates(&self) -> Result<usize, String> {
+        Ok(self.max_block_templates)
+    }
 }
@@ -0,0 +1,232 @@
+use super::{
+    block::{Block, BlockConfigurer, BlockHeader},
+    pow,
+};
+use primitive_types::U256;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+// Workers add to the shared hash counter and look for cancellation once per batch of hashes.
+const HASH_BATCH: u64 = 1024;
+
+// Header of a block the node is ready to publish, for miners outside the node. A solution is the nonce
+// for which the SHA3 of the encoded header is at or below the target, handed back with the template id.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BlockTemplate {
+    pub id: String,
+    pub header: BlockHeader,
+    pub target: U256,
+}
+
+impl BlockTemplate {
+    pub fn new(block: &Block) -> Self {
+        let mut header = block.header();
+        header.set_nonce(0);
+        Self {
+            id: header.calculate_hash(),
+            target: pow::target_from_bits(header.bits()),
+            header,
+        }
+    }
+
+    pub fn hash_with_nonce(&self, nonce: usize) -> String {
+        let mut header = self.header.clone();
+        header.set_nonce(nonce);
+        header.calculate_hash()
+    }
+
+    pub fn is_solved_by(&self, nonce: usize) -> bool {
+        pow::hash_meets_target(&self.hash_with_nonce(nonce), self.target)
+    }
+}
+
+#[derive(Debug)]
+struct MiningJob {
+    id: u64,
+    block: Block,
+    result_sender: UnboundedSender<Block>,
+}
+
+// State the pool handles share with the worker threads.
+#[derive(Debug)]
+struct JobSlot {
+    threads: usize,
+    job: Mutex<Option<MiningJob>>,
+    posted: Condvar,
+    job_id: AtomicU64,
+    hash_count: AtomicU64,
+    // Set once the last pool handle is dropped. Workers exit instead of waiting for another job.
+    stopped: AtomicBool,
+}
+
+impl JobSlot {
+    fn work(&self, index: usize) {
+        let mut last_job = 0;
+        loop {
+            let (id, mut block, result_sender) = {
+                let guard = self
+                    .posted
+                    .wait_while(self.job.lock().unwrap(), |job| {
+                        !self.stopped.load(Ordering::SeqCst)
+                            && job.as_ref().is_none_or(|job| job.id == last_job)
+                    })
+                    .unwrap();
+                if self.stopped.load(Ordering::SeqCst) {
+                    return;
+                }
+                let job = guard.as_ref().unwrap();
+                (job.id, job.block.clone(), job.result_sender.clone())
+            };
+            last_job = id;
+
+            let target = pow::target_from_bits(block.bits());
+            let mut nonce = index;
+            let mut hashes = 0;
+            loop {
+                block.set_nonce(nonce);
+                let hash = block.calculate_hash();
+                if pow::hash_meets_target(&hash, target) {
+                    block.set_hash(hash);
+                    let _ = result_sender.send(block);
+                    break;
+                }
+
+                nonce = nonce.wrapping_add(self.threads);
+                hashes += 1;
+                if hashes % HASH_BATCH == 0 {
+                    self.hash_count.fetch_add(HASH_BATCH, Ordering::Relaxed);
+                    if self.job_id.load(Ordering::SeqCst) != id {
+                        break;
+                    }
+                }
+            }
+            self.hash_count
+                .fetch_add(hashes % HASH_BATCH, Ordering::Relaxed);
+        }
+    }
+
+    // Replaces the job in progress, if any. Workers notice within one batch of hashes.
+    fn replace_job(&self, build: impl FnOnce(u64) -> Option<MiningJob>) {
+        let mut job = self.job.lock().unwrap();
+        let id = self.job_id.fetch_add(1, Ordering::SeqCst) + 1;
+        *job = build(id);
+        self.posted.notify_all();
+    }
+}
+
+// Owns the worker threads, stopping and joining them when the last pool handle goes away.
+#[derive(Debug)]
+struct Workers {
+    slot: Arc<JobSlot>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        self.slot.stopped.store(true, Ordering::SeqCst);
+        self.slot.replace_job(|_| None);
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Long-lived mining threads sharing one job at a time. Worker `i` of `n` tries nonces i, i + n, i + 2n, ...
+#[derive(Debug, Clone)]
+pub struct MinerPool {
+    workers: Arc<Workers>,
+    hashrate: Arc<AtomicU64>,
+}
+
+impl MinerPool {
+    pub fn new(threads: usize) -> Self {
+        let slot = Arc::new(JobSlot {
+            threads: threads.max(1),
+            job: Mutex::new(None),
+            posted: Condvar::new(),
+            job_id: AtomicU64::new(0),
+            hash_count: AtomicU64::new(0),
+            stopped: AtomicBool::new(false),
+        });
+
+        let handles = (0..slot.threads)
+            .map(|index| {
+                let slot = slot.clone();
+                thread::Builder::new()
+                    .name(format!("miner-{}", index))
+                    .spawn(move || slot.work(index))
+                    .unwrap()
+            })
+            .collect();
+        Self {
+            workers: Arc::new(Workers { slot, handles }),
+            hashrate: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    // Hands the block to every worker, replacing any job in progress. Resolves to None once the job is cancelled.
+    pub async fn mine(&self, block: Block) -> Option<Block> {
+        let (result_sender, mut result_receiver) = mpsc::unbounded_channel();
+        let started_at = Instant::now();
+        let hashes_before = self.workers.slot.hash_count.load(Ordering::Relaxed);
+        self.workers.slot.replace_job(|id| {
+            Some(MiningJob {
+                id,
+                block,
+                result_sender,
+            })
+        });
+
+        // Every worker holds a sender for the job, so the channel closes once all of them gave up on it.
+        let mined = result_receiver.recv().await;
+        if mined.is_some() {
+            self.cancel();
+        }
+
+        let hashes = self.workers.slot.hash_count.load(Ordering::Relaxed) - hashes_before;
+        let elapsed = started_at.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.hashrate
+                .store((hashes as f64 / elapsed) as u64, Ordering::Relaxed);
+        }
+        mined
+    }
+
+    // Stops the current job, if any. Workers notice within one batch of hashes.
+    pub fn cancel(&self) {
+        self.workers.slot.replace_job(|_| None);
+    }
+
+    // Hashes per second measured over the last finished or cancelled job.
+    pub fn hashrate(&self) -> u64 {
+        self.hashrate.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn mined_blocks_meet_their_target() {
+        let pool = MinerPool::new(2);
+        let mut block = Block::default();
+        // Four leading zero bits, so a solution turns up within a few dozen hashes.
+        block.set_bits(pow::bits_from_target(U256::MAX >> 4));
+
+        let mined = pool.mine(block.clone()).await.unwrap();
+        assert_eq!(mined.hash(), mined.calculate_hash());
+        assert!(pow::hash_meets_target(&mined.hash(), pow::target_from_bits(block.bits())));
+        assert!(BlockTemplate::new(&block).is_solved_by(mined.nonce()));
+
+        // Dropping the last handle stops and joins the workers.
+        drop(pool);
+    }
+}
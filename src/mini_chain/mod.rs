@@ -1,7 +1,15 @@
 pub mod address;
 pub mod block;
 pub mod chain;
+pub mod codec;
+pub mod genesis;
 pub mod mempool;
+pub mod merkle;
 pub mod metadata;
+pub mod miner;
 pub mod node;
+pub mod pow;
+pub mod state;
+pub mod store;
 pub mod transaction;
+pub mod vote;
@@ -1,26 +1,32 @@
 use super::{
     address::Address,
     block::{Block, BlockConfigurer},
-    chain::{Blockchain, BlockchainOperation},
+    chain::{self, Blockchain, BlockchainOperation, ChainUpdate, Receipt, Reorg},
+    genesis::GenesisConfig,
     mempool::{MemPool, MemPoolOperation},
     metadata::{ChainMetaData, ChainMetaDataOperation},
-    transaction::{Transaction, TxExisting},
+    miner::{BlockTemplate, MinerPool},
+    pow,
+    state::WorldStateOperation,
+    store::{BlockStore, BlockStoreOperation},
+    transaction::{Transaction, TxExisting, TxStatus},
+    vote::{BlockVerifyTx, Equivocation, VoteBook, VoteBookOperation},
 };
 use async_channel::{Receiver, Sender};
 use async_trait::async_trait;
-use sha3::{Digest, Sha3_256};
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{
-    sync::RwLock,
+    sync::{broadcast, RwLock},
     time::{sleep, timeout},
 };
 
-#[derive(Debug, Clone)]
-pub struct BlockVerifyTx {
-    pub block_hash: String,
-    pub verified: bool,
-}
-
 #[derive(Debug, Clone)]
 pub struct GetNonExistingBlockTx {
     hash_key: String,
@@ -30,12 +36,13 @@ pub struct GetNonExistingBlockTx {
 #[derive(Debug, Clone)]
 struct StagedBlockStatus {
     block: Block,
-    handsup: u64,
 }
 
 #[derive(Debug, Clone)]
 pub struct Node {
     pub address: Address,
+    // Derived from the genesis block; peers and blocks of other chains are refused.
+    pub chain_id: u64,
 
     pub client_tx_sender: Sender<Transaction>,
     pub client_tx_receiver: Receiver<Transaction>,
@@ -59,18 +66,29 @@ pub struct Node {
     pub net_block_verify_tx_sender: Sender<BlockVerifyTx>,
     pub net_non_existing_block_request_sender: Sender<GetNonExistingBlockTx>,
 
+    pub reorg_sender: broadcast::Sender<Reorg>,
+
+    miner_pool: MinerPool,
+    templates: Arc<RwLock<VecDeque<(String, Block)>>>,
     stagepool: Arc<RwLock<HashMap<String, StagedBlockStatus>>>,
+    // Validator approvals above the finalized height, and evidence of equivocation.
+    votes: Arc<RwLock<VoteBook>>,
     mempool: Arc<RwLock<MemPool>>,
     chain: Arc<RwLock<Blockchain>>,
+    store: Arc<RwLock<BlockStore>>,
+    // Where the mempool is dumped on shutdown, unless mempool persistence is turned off.
+    mempool_path: Option<String>,
 }
 
 impl Node {
     pub fn new(
+        address: Address,
         net_mined_block_sender: Sender<Block>,
         net_block_verify_tx_sender: Sender<BlockVerifyTx>,
         net_non_existing_block_request_sender: Sender<GetNonExistingBlockTx>,
+        genesis: GenesisConfig,
+        store_path: String,
     ) -> Self {
-        let address = Address::new();
         let (client_tx_sender, client_tx_receiver) = async_channel::unbounded();
         let (proposed_block_sender, proposed_block_receiver) = async_channel::unbounded();
         let (mined_block_sender, mined_block_receiver) = async_channel::unbounded();
@@ -78,8 +96,22 @@ impl Node {
         let (non_existing_block_sender, non_existing_block_receiver) = async_channel::unbounded();
         let (non_existing_block_request_sender, non_existing_block_request_receiver) =
             async_channel::unbounded();
+        let (reorg_sender, _) = broadcast::channel(16);
+        let miner_threads = ChainMetaData::default().get_miner_threads().unwrap();
+        let mempool_path = ChainMetaData::default()
+            .get_persist_mempool()
+            .unwrap()
+            .then(|| {
+                Path::new(&store_path)
+                    .with_extension("mempool")
+                    .to_string_lossy()
+                    .to_string()
+            });
+        let (chain, store) = Node::load_chain(&genesis, &store_path).unwrap();
+        let votes = VoteBook::new(chain.get_validators(), chain.get_quorum());
         Self {
             address,
+            chain_id: chain.get_chain_id(),
 
             client_tx_sender,
             client_tx_receiver,
@@ -103,40 +135,467 @@ impl Node {
             net_block_verify_tx_sender,
             net_non_existing_block_request_sender,
 
+            reorg_sender,
+
+            miner_pool: MinerPool::new(miner_threads),
+            templates: Arc::new(RwLock::new(VecDeque::new())),
             mempool: Arc::new(RwLock::new(MemPool::default())),
-            chain: Arc::new(RwLock::new(Blockchain::default())),
+            chain: Arc::new(RwLock::new(chain)),
+            store: Arc::new(RwLock::new(store)),
+            mempool_path,
             stagepool: Arc::new(RwLock::new(HashMap::new())),
+            votes: Arc::new(RwLock::new(votes)),
         }
     }
 }
 
 impl Node {
-    fn verify_block_hash(hash: String, difficulty: usize) -> bool {
-        let hash_binding = hash.as_str();
-        &hash_binding[0..difficulty] == "0".repeat(difficulty)
-    }
-
-    fn calculate_block_hash(block: Block) -> String {
-        let mut hasher = Sha3_256::new();
-
-        let hash_str = format!(
-            "{}{}{}{}{}{}",
-            block.builder().unwrap(),
-            block.sequence().unwrap(),
-            block.timestamp(),
-            block.tx_count(),
-            block.nonce(),
-            block.prev_hash()
+    // Rebuilds the chain from the block store, validating every block again on the way to the tip.
+    // Blocks that no longer validate are dropped from the store. A store holding blocks of another chain
+    // was written under a different genesis config and is left untouched.
+    fn load_chain(
+        genesis: &GenesisConfig,
+        store_path: &str,
+    ) -> Result<(Blockchain, BlockStore), String> {
+        let mut store = BlockStore::open(store_path)?;
+        let mut chain = Blockchain::new(genesis);
+
+        let stored_blocks = store.get_blocks()?;
+        if let Some((block, _)) = stored_blocks
+            .iter()
+            .find(|(block, _)| block.chain_id() != chain.get_chain_id())
+        {
+            return Err(format!(
+                "{} holds blocks of chain {:016x}, but the genesis config is for chain {:016x}",
+                store_path,
+                block.chain_id(),
+                chain.get_chain_id()
+            ));
+        }
+        let stored_count = stored_blocks.len();
+        let mut kept = vec![];
+        for (block, _) in stored_blocks {
+            if !Node::verify_block_content(&block) {
+                continue;
+            }
+            let hash = block.hash();
+            match chain.add_block(block.clone()) {
+                Ok(ChainUpdate::Extended(_))
+                | Ok(ChainUpdate::SideBranch(_))
+                | Ok(ChainUpdate::Reorganized(_)) => {
+                    kept.push((block, chain.get_height(&hash).unwrap_or_default()));
+                }
+                _ => {}
+            }
+        }
+
+        // Finality is recorded next to the blocks, so a restart does not reopen what a quorum settled.
+        if let Some(finalized) = store.get_finalized() {
+            if let Err(e) = chain.finalize_block(&finalized) {
+                println!("Failed restoring the finalized block:\n{:?}", e);
+            }
+        }
+
+        if kept.len() < stored_count {
+            println!(
+                "Dropped {} stored blocks that no longer validate",
+                stored_count - kept.len()
+            );
+            if let Err(e) = store.rewrite(&kept) {
+                println!("Failed rewriting the block store:\n{:?}", e);
+            }
+        }
+        Ok((chain, store))
+    }
+
+    // Writes a block that joined the tree to the block store.
+    async fn persist_block(&self, block: &Block, chain: &Blockchain) {
+        let height = chain.get_height(&block.hash()).unwrap_or_default();
+        let mut proc_store = self.store.write().await;
+        if let Err(e) = proc_store.append(block, height) {
+            println!("Failed storing a block:\n{:?}", e);
+        }
+    }
+
+    // Writes the pending transactions next to the block store, replacing the previous dump in a single rename.
+    // The temporary file is named after the dump, apart from the one the block store rewrites through.
+    async fn save_mempool(&self) -> Result<(), String> {
+        let Some(path) = &self.mempool_path else {
+            return Ok(());
+        };
+        let bytes = {
+            let proc_mempool = self.mempool.read().await;
+            proc_mempool.dump_transactions().await
+        };
+
+        let tmp_path = format!("{}.tmp", path);
+        {
+            let mut tmp_file = File::create(&tmp_path).map_err(|e| e.to_string())?;
+            tmp_file.write_all(&bytes).map_err(|e| e.to_string())?;
+            tmp_file.sync_all().map_err(|e| e.to_string())?;
+        }
+        fs::rename(&tmp_path, path).map_err(|e| e.to_string())
+    }
+
+    // Re-admits the transactions dumped at the last shutdown, checked against the recovered chain state.
+    // A dump that cannot be decoded is set aside rather than deleted.
+    async fn restore_mempool(&self) {
+        let Some(path) = &self.mempool_path else {
+            return;
+        };
+        let Ok(bytes) = fs::read(path) else {
+            return;
+        };
+
+        let state = {
+            let proc_chain = self.chain.read().await;
+            proc_chain.get_state().unwrap()
+        };
+        let mut proc_mempool = self.mempool.write().await;
+        match proc_mempool
+            .restore_transactions(&bytes, &state, self.chain_id)
+            .await
+        {
+            Ok(restored) => {
+                println!("Restored {} transactions into the mempool", restored);
+                let _ = fs::remove_file(path);
+            }
+            Err(e) => {
+                println!("Failed restoring the mempool:\n{:?}", e);
+                let _ = fs::rename(path, format!("{}.corrupt", path));
+            }
+        }
+    }
+
+    // Validators approve at most one block per height and round, so a block arriving after another one
+    // at its height is not approved even when it is valid, until that round is over. The approval is
+    // recorded and tallied before it is broadcast, as it may complete the quorum; returns whether it did.
+    async fn send_vote(&self, block: &Block, verified: bool) -> bool {
+        let is_validator = {
+            let proc_chain = self.chain.read().await;
+            proc_chain
+                .get_validators()
+                .iter()
+                .any(|validator| validator == self.address.get_public_address())
+        };
+        if !is_validator {
+            return false;
+        }
+
+        let height = block.sequence().unwrap_or_default();
+        let vote = {
+            let mut proc_votes = self.votes.write().await;
+            let round = proc_votes.current_round(height);
+            if verified && proc_votes.has_voted(self.address.get_public_address(), height, round) {
+                return false;
+            }
+            let vote = BlockVerifyTx::new(
+                self.chain_id,
+                block.hash(),
+                height,
+                round,
+                verified,
+                &self.address,
+            );
+            let _ = proc_votes.add_vote(&vote);
+            vote
+        };
+        let committed = verified && self.tally_votes(&block.hash()).await;
+        let _ = self.net_block_verify_tx_sender.send(vote).await;
+        committed
+    }
+
+    // Once a round at the height is over without a quorum, approves again in the next one. Every
+    // validator picks the valid staged block with the lowest hash, so the next round converges.
+    async fn vote_in_next_round(&self, height: u64) {
+        {
+            let proc_votes = self.votes.read().await;
+            let round = proc_votes.current_round(height);
+            if round == 0 || proc_votes.has_voted(self.address.get_public_address(), height, round) {
+                return;
+            }
+        }
+
+        let mut candidates: Vec<Block> = {
+            let proc_stagepool = self.stagepool.read().await;
+            proc_stagepool
+                .values()
+                .filter(|staged| staged.block.sequence() == Some(height))
+                .map(|staged| staged.block.clone())
+                .collect()
+        };
+        candidates.sort_by_key(|block| block.hash());
+        for block in candidates {
+            if self.verifier(block.clone()).await {
+                if self.send_vote(&block, true).await {
+                    let children = self.staged_children(&block.hash()).await;
+                    self.stage_blocks(children).await;
+                }
+                return;
+            }
+        }
+    }
+
+    // Once a quorum of validators approved a block at its height, commits it if it is still staged and
+    // finalizes it. Returns whether the block is in the chain and finalized.
+    async fn tally_votes(&self, hash: &str) -> bool {
+        let mut proc_stagepool = self.stagepool.write().await;
+        let mut proc_votes = self.votes.write().await;
+        let mut proc_chain = self.chain.write().await;
+        let height = match proc_stagepool.get(hash) {
+            Some(staged) => staged.block.sequence(),
+            None => proc_chain.get_height(hash),
+        };
+        if !height.is_some_and(|height| proc_votes.has_quorum(hash, height)) {
+            return false;
+        }
+
+        if let Some(staged) = proc_stagepool.remove(hash) {
+            let mut proc_mempool = self.mempool.write().await;
+            self.commit_block(staged.block, &mut proc_chain, &mut proc_mempool)
+                .await;
+        }
+        if !proc_chain.contains_block(hash) {
+            return false;
+        }
+
+        if let Err(e) = proc_chain.finalize_block(hash) {
+            println!("Failed finalizing a block:\n{:?}", e);
+            return false;
+        }
+
+        let finalized = proc_chain.get_finalized();
+        if let Err(e) = self.store.write().await.set_finalized(&finalized) {
+            println!("Failed storing the finalized block:\n{:?}", e);
+        }
+
+        // Votes and competing blocks up to the finalized height are turned away from now on.
+        let finalized_height = proc_chain.get_height(&finalized).unwrap_or_default();
+        proc_votes.prune(finalized_height);
+        proc_stagepool.retain(|_, staged| staged.block.sequence() > Some(finalized_height));
+        true
+    }
+
+    // Verifies staged blocks and votes on them. A block whose parent is staged waits with it and is
+    // verified once the parent is committed. A block whose parent is unknown waits in the chain's orphan
+    // pool while the parent is asked from peers, and is staged once the parent is, so blocks filling a
+    // gap go through the same votes as any other.
+    async fn stage_blocks(&self, mut blocks: Vec<Block>) {
+        while let Some(block) = blocks.pop() {
+            // Nothing is kept before its proof of work and contents check out.
+            if !Node::verify_block_content(&block) {
+                continue;
+            }
+
+            let hash = block.hash();
+            let prev_hash = block.prev_hash();
+            let (parent_committed, orphaned) = {
+                let mut proc_stagepool = self.stagepool.write().await;
+                let mut proc_chain = self.chain.write().await;
+                if proc_chain.contains_block(&hash) {
+                    continue;
+                }
+                let parent_committed = proc_chain.contains_block(&prev_hash);
+                if !parent_committed && !proc_stagepool.contains_key(&prev_hash) {
+                    // The parent is unknown, so the block waits in the chain's bounded orphan pool.
+                    match proc_chain.add_block(block.clone()) {
+                        Ok(ChainUpdate::Orphaned(missing)) => (false, Some(missing)),
+                        Ok(_) => continue,
+                        Err(e) => {
+                            println!("Refused a block without a parent:\n{:?}", e);
+                            continue;
+                        }
+                    }
+                } else {
+                    proc_stagepool.insert(
+                        hash.clone(),
+                        StagedBlockStatus {
+                            block: block.clone(),
+                        },
+                    );
+                    // Orphans waiting on this block are staged behind it now.
+                    blocks.extend(proc_chain.take_orphans(&hash));
+                    (parent_committed, None)
+                }
+            };
+            if let Some(missing) = orphaned {
+                self.request_block(missing).await;
+                continue;
+            }
+
+            let mut committed = false;
+            if parent_committed {
+                let verified = self.verifier(block.clone()).await;
+                committed = self.send_vote(&block, verified).await;
+            }
+
+            // Votes of faster validators may have arrived before the block.
+            if committed || self.tally_votes(&hash).await {
+                blocks.extend(self.staged_children(&hash).await);
+            }
+        }
+    }
+
+    async fn staged_children(&self, hash: &str) -> Vec<Block> {
+        let proc_stagepool = self.stagepool.read().await;
+        proc_stagepool
+            .values()
+            .filter(|staged| staged.block.prev_hash() == hash)
+            .map(|staged| staged.block.clone())
+            .collect()
+    }
+
+    fn verify_block_hash(hash: String, bits: u32) -> bool {
+        pow::hash_meets_target(&hash, pow::target_from_bits(bits))
+    }
+
+    // Gives the transactions of disconnected blocks back to the mempool, then approves the ones of newly
+    // connected blocks, so transactions on both branches end up approved rather than reopened.
+    async fn process_reorg(&self, reorg: Reorg, chain: &Blockchain, mempool: &mut MemPool) {
+        let connected_hashes: Vec<String> = reorg
+            .connected
+            .iter()
+            .flat_map(|block| block.tx_hashes())
+            .collect();
+
+        for block in reorg.disconnected.iter() {
+            let _ = mempool.reopen_transactions(block.tx_hashes()).await;
+            for tx in block.transactions() {
+                if tx.is_coinbase() || connected_hashes.contains(&tx.hash) {
+                    continue;
+                }
+                let account = chain.get_account(&tx.signer).unwrap();
+                let _ = mempool.add_transaction(tx, &account).await;
+            }
+        }
+
+        for block in reorg.connected.iter() {
+            let height = chain.get_height(&block.hash()).unwrap_or_default();
+            let _ = mempool.approve_transactions(block, height).await;
+        }
+
+        println!(
+            "Chain reorganized from {} to {} at {}",
+            reorg.old_tip, reorg.new_tip, reorg.common_ancestor
         );
-        hasher.update(hash_str);
+        let _ = self.reorg_sender.send(reorg);
+    }
+
+    // Keeps a freshly built block for template submissions, forgetting templates built on an older tip
+    // and the oldest ones past `max_block_templates`. The transactions of forgotten templates are handed
+    // back to the mempool for later templates.
+    async fn store_template(&self, block: &Block) -> BlockTemplate {
+        let template = BlockTemplate::new(block);
+        let max_templates = ChainMetaData::default().get_max_block_templates().unwrap();
+        let mut dropped = vec![];
+        {
+            let mut proc_templates = self.templates.write().await;
+            let (kept, stale) = proc_templates
+                .drain(..)
+                .partition(|(_, template_block)| template_block.prev_hash() == block.prev_hash());
+            *proc_templates = kept;
+            dropped.extend(stale);
+            proc_templates.push_back((template.id.clone(), block.clone()));
+            while proc_templates.len() > max_templates {
+                dropped.extend(proc_templates.pop_front());
+            }
+        }
+
+        let hashes: Vec<String> = dropped
+            .iter()
+            .flat_map(|(_, template_block)| template_block.tx_hashes())
+            .collect();
+        let _ = self.mempool.write().await.release_transactions(hashes).await;
+        template
+    }
+
+    async fn request_block(&self, hash: String) {
+        let get_block_request = GetNonExistingBlockTx {
+            hash_key: hash,
+            block_sender: self.non_existing_block_sender.clone(),
+        };
+
+        self.net_non_existing_block_request_sender
+            .send(get_block_request)
+            .await
+            .unwrap();
+    }
+
+    // Adds a block to the chain and keeps the mempool in line with the new canonical chain.
+    // Blocks with an unknown parent wait in the orphan pool while the parent is requested from peers.
+    async fn commit_block(&self, block: Block, chain: &mut Blockchain, mempool: &mut MemPool) {
+        let hash = block.hash();
+        let update = match chain.add_block(block.clone()) {
+            Ok(update) => update,
+            Err(e) => {
+                println!("Failed adding a block to the chain:\n{:?}", e);
+                return;
+            }
+        };
+
+        let mut updates = vec![];
+        if let ChainUpdate::Orphaned(missing) = &update {
+            self.request_block(missing.clone()).await;
+        } else if update != ChainUpdate::AlreadyKnown {
+            updates = chain.connect_orphans(&hash);
+        }
+        updates.insert(0, (block, update));
+
+        for (block, update) in updates {
+            if chain.contains_block(&block.hash()) {
+                self.persist_block(&block, chain).await;
+            }
+
+            // Whatever is being mined no longer builds on the tip.
+            if matches!(
+                update,
+                ChainUpdate::Extended(_) | ChainUpdate::Reorganized(_)
+            ) {
+                self.miner_pool.cancel();
+                self.templates.write().await.clear();
+            }
+
+            match update {
+                ChainUpdate::Extended(_) => {
+                    let height = chain.get_height(&block.hash()).unwrap_or_default();
+                    let _ = mempool.approve_transactions(&block, height).await;
+                    let _ = mempool.release_all_transactions().await;
+                }
+                ChainUpdate::Reorganized(reorg) => {
+                    self.process_reorg(reorg, chain, mempool).await;
+                    let _ = mempool.release_all_transactions().await;
+                }
+                // Side branch transactions stay pending until their branch becomes canonical.
+                ChainUpdate::SideBranch(_)
+                | ChainUpdate::Orphaned(_)
+                | ChainUpdate::AlreadyKnown => {}
+            }
+        }
+    }
+
+    // Checks that do not depend on the parent block: header hash, proof of work, merkle root and transactions.
+    fn verify_block_content(block: &Block) -> bool {
+        if block.tx_count() != block.transactions().len()
+            || block.merkle_root() != block.calculate_merkle_root()
+        {
+            return false;
+        }
 
         for tx in block.transactions() {
-            let hash_str = format!("{:?}", tx);
-            hasher.update(hash_str);
+            if tx.verify_hash().is_err() {
+                return false;
+            }
+            if !tx.is_coinbase() && tx.verify_signature().is_err() {
+                return false;
+            }
         }
 
-        let hash = format!("{:x}", hasher.finalize());
-        hash
+        let hash_value = block.calculate_hash();
+        if hash_value != block.hash() {
+            return false;
+        }
+
+        Node::verify_block_hash(hash_value, block.bits())
     }
 }
 
@@ -144,20 +603,43 @@ impl Node {
 #[async_trait]
 pub trait TxProcesser {
     async fn add_tx_to_pool(&self);
+    async fn get_transaction_status(&self, hash: &str) -> Option<TxStatus>;
+    async fn get_next_nonce(&self, signer: &str) -> usize;
     async fn run_tx_receiver(&self) -> Result<(), String>;
 }
 
 #[async_trait]
 impl TxProcesser for Node {
     async fn add_tx_to_pool(&self) {
-        loop {
-            if let Ok(tx) = self.client_tx_receiver.recv().await {
-                let mut proc_mempool = self.mempool.write().await;
-                let _ = proc_mempool.add_transaction(tx.clone()).await;
+        while let Ok(tx) = self.client_tx_receiver.recv().await {
+            if tx.chain_id != self.chain_id {
+                continue;
             }
+            let account = {
+                let proc_chain = self.chain.read().await;
+                proc_chain.get_account(&tx.signer).unwrap()
+            };
+            let mut proc_mempool = self.mempool.write().await;
+            let _ = proc_mempool.add_transaction(tx.clone(), &account).await;
         }
     }
 
+    async fn get_transaction_status(&self, hash: &str) -> Option<TxStatus> {
+        let proc_mempool = self.mempool.read().await;
+        proc_mempool.get_transaction_status(hash).await
+    }
+
+    // The nonce a new transaction of the signer takes: the committed account nonce, moved past the
+    // signer's transactions already pending in the mempool.
+    async fn get_next_nonce(&self, signer: &str) -> usize {
+        let account_nonce = {
+            let proc_chain = self.chain.read().await;
+            proc_chain.get_account(signer).unwrap_or_default().nonce
+        };
+        let proc_mempool = self.mempool.read().await;
+        proc_mempool.get_next_nonce(signer, account_nonce).await
+    }
+
     async fn run_tx_receiver(&self) -> Result<(), String> {
         let node = self.clone();
         tokio::spawn(async move {
@@ -190,29 +672,58 @@ impl Proposer for Node {
 
         let mut block = Block::default();
 
-        block.set_block_builder(self.address.get_public_address().to_string());
+        let builder = self.address.get_public_address().to_string();
+        block.set_block_builder(builder.clone());
 
         let proc_chain = self.chain.write().await;
-        block.set_block_sequence(proc_chain.get_sequence().unwrap());
+        block.set_chain_id(proc_chain.get_chain_id());
 
         let mut proc_mempool = self.mempool.write().await;
 
+        // The coinbase takes one of the block's transaction slots.
+        let mut included = vec![];
         match timeout(
             Duration::from_millis(block_tx_pickup_period as u64),
-            async { proc_mempool.pickup_transaction(block_size).await },
+            async {
+                proc_mempool
+                    .pickup_transaction(block_size.saturating_sub(1))
+                    .await
+            },
         )
         .await
         {
             Ok(Ok(transactions)) => {
+                // Skip transfers the sender cannot afford at the current leaf, and hand them back.
+                let mut state = proc_chain.get_state().unwrap();
+                let mut skipped = vec![];
                 for tx in transactions.iter() {
-                    block.add_transaction(tx.clone());
+                    if state.apply_transaction(tx).is_ok() {
+                        included.push(tx.clone());
+                    } else {
+                        skipped.push(tx.hash.clone());
+                    }
                 }
+                let _ = proc_mempool.release_transactions(skipped).await;
             }
             Ok(Err(_)) => {}
             Err(_) => {}
         };
 
         let prev_hash = proc_chain.get_leaf().unwrap();
+        let height = proc_chain.get_leaf_height() + 1;
+        let fees: usize = included.iter().map(|tx| tx.fee).sum();
+        block.add_transaction(Transaction::new_coinbase(
+            proc_chain.get_chain_id(),
+            builder,
+            chain::block_subsidy(height)? + fees,
+            height,
+        ));
+        for tx in included {
+            block.add_transaction(tx);
+        }
+
+        block.set_block_sequence(height);
+        block.set_bits(proc_chain.next_bits(&prev_hash)?);
         block.set_prev_hash(prev_hash);
 
         Ok(block)
@@ -244,6 +755,7 @@ impl Proposer for Node {
             .await
             {
                 Ok(Ok(block)) => {
+                    self.store_template(&block).await;
                     self.send_propose_block(block).await.unwrap();
                 }
                 Ok(Err(e)) => {
@@ -267,12 +779,14 @@ impl Proposer for Node {
 }
 
 // Receives a proposed block and mine it by calculating block hash.
+// The search runs on the miner pool threads and gives way to a newer proposal or a new chain tip.
 #[async_trait]
 pub trait Miner {
     async fn run_miner(&mut self) -> Result<(), String>;
     async fn mine_block(&mut self);
-    async fn mining(&self, block: &mut Block) -> Result<Block, String>;
-    async fn send_mined_block(&mut self, block: Block) -> Result<(), String>;
+    async fn mining(&self, block: Block) -> Result<Option<Block>, String>;
+    async fn send_mined_block(&self, block: Block) -> Result<(), String>;
+    fn hashrate(&self) -> u64;
 }
 
 #[async_trait]
@@ -287,44 +801,186 @@ impl Miner for Node {
     }
 
     async fn mine_block(&mut self) {
+        let mut next_block = None;
         loop {
-            if let Ok(mut block) = self.proposed_block_receiver.recv().await {
-                let m_block = self.mining(&mut block).await.unwrap();
-                self.send_mined_block(m_block).await.unwrap();
+            let block = match next_block.take() {
+                Some(block) => block,
+                None => match self.proposed_block_receiver.recv().await {
+                    Ok(block) => block,
+                    Err(_) => break,
+                },
+            };
+
+            // A job that ends without a block hands its transactions back to later templates.
+            let tx_hashes = block.tx_hashes();
+            let mined = tokio::select! {
+                mined = self.mining(block) => mined,
+                Ok(block) = self.proposed_block_receiver.recv() => {
+                    self.miner_pool.cancel();
+                    next_block = Some(block);
+                    Ok(None)
+                }
+            };
+
+            match mined {
+                Ok(Some(m_block)) => self.send_mined_block(m_block).await.unwrap(),
+                Ok(None) => {
+                    let _ = self.mempool.write().await.release_transactions(tx_hashes).await;
+                }
+                Err(e) => {
+                    println!("Failed mining a block:\n{:?}", e);
+                    let _ = self.mempool.write().await.release_transactions(tx_hashes).await;
+                }
             }
         }
     }
 
-    async fn mining(&self, block: &mut Block) -> Result<Block, String> {
-        let block_difficulty = {
-            let chain_metadata = ChainMetaData::default();
-            chain_metadata.get_block_difficulty().unwrap()
-        };
-
-        let mut hash_value = Node::calculate_block_hash(block.clone());
-        while !Node::verify_block_hash(hash_value.clone(), block_difficulty) {
-            block.inc_nonce();
-            hash_value = Node::calculate_block_hash(block.clone());
+    async fn mining(&self, block: Block) -> Result<Option<Block>, String> {
+        if pow::target_from_bits(block.bits()).is_zero() {
+            return Err(format!("Invalid difficulty bits {:#010x}", block.bits()));
         }
-        block.set_hash(hash_value);
-        Ok(block.clone())
+        Ok(self.miner_pool.mine(block).await)
     }
 
-    async fn send_mined_block(&mut self, block: Block) -> Result<(), String> {
-        let mut proc_stagepool = self.stagepool.write().await;
-        proc_stagepool.insert(
-            block.hash().clone(),
-            StagedBlockStatus {
-                block: block.clone(),
-                handsup: 1,
-            },
-        );
+    // The builder stages its block and votes for it like any other validator.
+    async fn send_mined_block(&self, block: Block) -> Result<(), String> {
+        {
+            let mut proc_stagepool = self.stagepool.write().await;
+            proc_stagepool.insert(
+                block.hash().clone(),
+                StagedBlockStatus {
+                    block: block.clone(),
+                },
+            );
+        }
         self.net_mined_block_sender
             .send(block.clone())
             .await
             .unwrap();
+        if self.send_vote(&block, true).await {
+            let children = self.staged_children(&block.hash()).await;
+            self.stage_blocks(children).await;
+        }
         Ok(())
     }
+
+    fn hashrate(&self) -> u64 {
+        self.miner_pool.hashrate()
+    }
+}
+
+// Hands out block templates to miners outside the node and broadcasts the blocks they solve.
+#[async_trait]
+pub trait TemplateProvider {
+    async fn get_block_template(&self) -> Result<BlockTemplate, String>;
+    async fn submit_block(&self, template_id: String, nonce: usize) -> Result<String, String>;
+}
+
+#[async_trait]
+impl TemplateProvider for Node {
+    async fn get_block_template(&self) -> Result<BlockTemplate, String> {
+        let block = self.build_block().await?;
+        Ok(self.store_template(&block).await)
+    }
+
+    async fn submit_block(&self, template_id: String, nonce: usize) -> Result<String, String> {
+        let template = self
+            .templates
+            .read()
+            .await
+            .iter()
+            .find(|(id, _)| *id == template_id)
+            .map(|(_, block)| block.clone());
+        let Some(mut block) = template else {
+            return Err(format!("Unknown block template {}", template_id));
+        };
+
+        let leaf = self.chain.read().await.get_leaf()?;
+        if block.prev_hash() != leaf {
+            return Err(format!("Block template {} no longer builds on the tip", template_id));
+        }
+
+        block.set_nonce(nonce);
+        block.set_hash(block.calculate_hash());
+        if !Node::verify_block_content(&block) {
+            return Err(format!("Nonce {} does not solve block template {}", nonce, template_id));
+        }
+
+        self.templates.write().await.retain(|(id, _)| *id != template_id);
+        // The internal miners are working on the same height; their result would only compete with this one.
+        self.miner_pool.cancel();
+        self.send_mined_block(block.clone()).await?;
+        Ok(block.hash())
+    }
+}
+
+// Lets clients follow their transactions into the canonical chain.
+#[async_trait]
+pub trait ReceiptProvider {
+    async fn get_receipt(&self, tx_hash: &str) -> Option<Receipt>;
+    async fn get_confirmations(&self, tx_hash: &str) -> u64;
+    async fn wait_for_confirmations(
+        &self,
+        tx_hash: &str,
+        confirmations: u64,
+    ) -> Result<Receipt, String>;
+}
+
+#[async_trait]
+impl ReceiptProvider for Node {
+    async fn get_receipt(&self, tx_hash: &str) -> Option<Receipt> {
+        let proc_chain = self.chain.read().await;
+        proc_chain.get_receipt(tx_hash)
+    }
+
+    async fn get_confirmations(&self, tx_hash: &str) -> u64 {
+        let proc_chain = self.chain.read().await;
+        proc_chain.get_confirmations(tx_hash)
+    }
+
+    // Resolves once the transaction is buried under enough blocks, with the receipt as of that moment.
+    // Fails once the transaction can no longer make it into the chain: rejected by the mempool, or
+    // unknown to both the chain and the mempool on two checks in a row, which leaves time for one just
+    // sent to reach the pool or one just disconnected by a reorg to be re-added.
+    async fn wait_for_confirmations(
+        &self,
+        tx_hash: &str,
+        confirmations: u64,
+    ) -> Result<Receipt, String> {
+        let block_gen_period = ChainMetaData::default().get_block_gen_period()?;
+        let mut unknown = false;
+        loop {
+            let in_chain = {
+                let proc_chain = self.chain.read().await;
+                if proc_chain.get_confirmations(tx_hash) >= confirmations.max(1) {
+                    return proc_chain
+                        .get_receipt(tx_hash)
+                        .ok_or(format!("Transaction {} has no receipt", tx_hash));
+                }
+                proc_chain.get_receipt(tx_hash).is_some()
+            };
+
+            if in_chain {
+                unknown = false;
+            } else {
+                let status = {
+                    let proc_mempool = self.mempool.read().await;
+                    proc_mempool.get_transaction_status(tx_hash).await
+                };
+                match status {
+                    Some(TxStatus::REJECTED(reason)) => {
+                        return Err(format!("Transaction {} was rejected: {:?}", tx_hash, reason));
+                    }
+                    Some(_) => unknown = false,
+                    None if unknown => {
+                        return Err(format!("Transaction {} is unknown to the node", tx_hash));
+                    }
+                    None => unknown = true,
+                }
+            }
+            sleep(Duration::from_millis(block_gen_period as u64)).await;
+        }
+    }
 }
 
 // Receives a mined block and verify it if it's valid block. If it's verified, add it to the chain.
@@ -339,68 +995,53 @@ pub trait Verifier {
 impl Verifier for Node {
     async fn verifier(&self, block: Block) -> bool {
         let proc_chain = self.chain.write().await;
-        if block.prev_hash() != proc_chain.get_leaf().unwrap() {
+        if block.chain_id() != proc_chain.get_chain_id() {
             return false;
         }
 
-        if block.sequence().unwrap() != proc_chain.get_sequence().unwrap() {
+        let prev_hash = block.prev_hash();
+        let Some(parent_height) = proc_chain.get_height(&prev_hash) else {
+            return false;
+        };
+        if block.sequence() != Some(parent_height + 1) {
             return false;
         }
 
-        let hash_value = Node::calculate_block_hash(block.clone());
-        if hash_value != block.hash() {
+        // A block may not be mined at an easier difficulty than the chain demands, nor carry a timestamp
+        // the chain would refuse.
+        if proc_chain.next_bits(&prev_hash) != Ok(block.bits())
+            || proc_chain.verify_timestamp(&block).is_err()
+        {
             return false;
         }
 
-        let block_difficulty = {
-            let chain_metadata = ChainMetaData::default();
-            chain_metadata.get_block_difficulty().unwrap()
+        if !Node::verify_block_content(&block) || proc_chain.verify_coinbase(&block).is_err() {
+            return false;
+        }
+
+        let Ok(mut state) = proc_chain.get_state_at(&prev_hash) else {
+            return false;
         };
+        if state.apply_block(&block).is_err() {
+            return false;
+        }
 
         let proc_pool = self.mempool.write().await;
-        for tx in block.transactions() {
-            if proc_pool.existing_transaction(tx.clone()).await == TxExisting::NONEXISTING {
+        for tx in block.transactions().into_iter().skip(1) {
+            if proc_pool.existing_transaction(tx).await == TxExisting::NONEXISTING {
                 return false;
             }
         }
 
-        return Node::verify_block_hash(hash_value, block_difficulty);
+        true
     }
 
     async fn verify_mined_block(&mut self) {
-        loop {
-            if let Ok(mined_block) = self.mined_block_receiver.recv().await {
-                if mined_block.builder() == Some(self.address.get_public_address().to_string()) {
-                    continue;
-                }
-
-                let mut proc_stagepool = self.stagepool.write().await;
-                proc_stagepool.insert(
-                    mined_block.hash().clone(),
-                    StagedBlockStatus {
-                        block: mined_block.clone(),
-                        handsup: 1,
-                    },
-                );
-
-                if self.verifier(mined_block.clone()).await {
-                    let _ = self
-                        .net_block_verify_tx_sender
-                        .send(BlockVerifyTx {
-                            block_hash: mined_block.hash().clone(),
-                            verified: true,
-                        })
-                        .await;
-                } else {
-                    let _ = self
-                        .net_block_verify_tx_sender
-                        .send(BlockVerifyTx {
-                            block_hash: mined_block.hash().clone(),
-                            verified: false,
-                        })
-                        .await;
-                }
+        while let Ok(mined_block) = self.mined_block_receiver.recv().await {
+            if mined_block.builder() == Some(self.address.get_public_address().to_string()) {
+                continue;
             }
+            self.stage_blocks(vec![mined_block]).await;
         }
     }
 
@@ -419,47 +1060,63 @@ impl Verifier for Node {
 pub trait ChainManager {
     async fn run_chain_manager(&self) -> Result<(), String>;
     async fn chain_manager(&mut self);
+    async fn get_evidence(&self) -> Vec<Equivocation>;
 }
 
 #[async_trait]
 impl ChainManager for Node {
+    // Counts one approval per validator, height and round. Votes with a bad signature, from outside the validator
+    // set, at or below the finalized height, or for a height the block is not at are dropped, and so
+    // are approvals of a second block at the same height and round, which go to the evidence log.
     async fn chain_manager(&mut self) {
-        loop {
-            if let Ok(block_verify_tx) = self.block_verify_tx_receiver.recv().await {
-                let mut proc_stagepool = self.stagepool.write().await;
-                if let Some(prev_block_status) = proc_stagepool.get_mut(&block_verify_tx.block_hash)
-                {
-                    if block_verify_tx.verified == false {
-                        continue;
-                    }
-                    prev_block_status.handsup += 1;
-
-                    let mut proc_chain = self.chain.write().await;
-                    if prev_block_status.handsup > (proc_chain.get_sequence().unwrap() * 2 / 3) {
-                        let prev_status =
-                            proc_stagepool.remove(&block_verify_tx.block_hash).unwrap();
-                        let _ = proc_chain.add_block(prev_status.block.clone());
-
-                        let mut proc_mempool = self.mempool.write().await;
-                        let _ = proc_mempool
-                            .remove_transactions(prev_status.block.tx_hashes().clone())
-                            .await;
-                    }
-                } else {
-                    let get_block_request = GetNonExistingBlockTx {
-                        hash_key: block_verify_tx.block_hash.clone(),
-                        block_sender: self.non_existing_block_sender.clone(),
-                    };
-
-                    self.net_non_existing_block_request_sender
-                        .send(get_block_request)
-                        .await
-                        .unwrap();
+        while let Ok(block_verify_tx) = self.block_verify_tx_receiver.recv().await {
+            if block_verify_tx.chain_id != self.chain_id {
+                continue;
+            }
+            let hash = block_verify_tx.block_hash.clone();
+
+            let block_height = {
+                let proc_stagepool = self.stagepool.read().await;
+                let proc_chain = self.chain.read().await;
+                let finalized_height = proc_chain
+                    .get_height(&proc_chain.get_finalized())
+                    .unwrap_or_default();
+                if block_verify_tx.height <= finalized_height {
+                    continue;
+                }
+                match proc_stagepool.get(&hash) {
+                    Some(staged) => staged.block.sequence(),
+                    None => proc_chain.get_height(&hash),
+                }
+            };
+            if block_height.is_some_and(|height| height != block_verify_tx.height) {
+                continue;
+            }
+
+            let counted = self.votes.write().await.add_vote(&block_verify_tx);
+            match counted {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    println!("Refused a vote:\n{:?}", e);
+                    continue;
                 }
             }
+
+            if block_height.is_none() {
+                self.request_block(hash).await;
+            } else if self.tally_votes(&hash).await {
+                let children = self.staged_children(&hash).await;
+                self.stage_blocks(children).await;
+            }
+            self.vote_in_next_round(block_verify_tx.height).await;
         }
     }
 
+    async fn get_evidence(&self) -> Vec<Equivocation> {
+        self.votes.read().await.get_evidence()
+    }
+
     async fn run_chain_manager(&self) -> Result<(), String> {
         let mut node = self.clone();
 
@@ -480,47 +1137,55 @@ pub trait BlockGetProcesser {
 
 #[async_trait]
 impl BlockGetProcesser for Node {
+    // Only committed blocks are served; staged ones have not been approved by a quorum.
     async fn request_processer(&self) {
-        loop {
-            if let Ok(request) = self.non_existing_block_request_receiver.recv().await {
-                let proc_stagepool = self.stagepool.write().await;
-                if let Some(block) = proc_stagepool.get(&request.hash_key) {
-                    let _ = request.block_sender.send(block.clone()).await.unwrap();
-                    break;
-                }
+        while let Ok(request) = self.non_existing_block_request_receiver.recv().await {
+            let proc_chain = self.chain.read().await;
+            if let Some(block) = proc_chain.get_block_by_hash(&request.hash_key) {
+                let _ = request
+                    .block_sender
+                    .send(StagedBlockStatus { block })
+                    .await;
             }
         }
     }
 
+    // Blocks received from peers are staged and voted on like freshly mined ones.
     async fn receive_block_processer(&self) {
-        loop {
-            if let Ok(block) = self.non_existing_block_receiver.recv().await {
-                let mut proc_stagepool = self.stagepool.write().await;
-                proc_stagepool.insert(block.block.hash(), block.clone());
-            }
+        while let Ok(staged) = self.non_existing_block_receiver.recv().await {
+            self.stage_blocks(vec![staged.block]).await;
         }
     }
 
     async fn run_get_processser(&self) -> Result<(), String> {
         let node = self.clone();
-
         tokio::spawn(async move {
             node.request_processer().await;
         });
 
+        let node = self.clone();
+        tokio::spawn(async move {
+            node.receive_block_processer().await;
+        });
+
         Ok(())
     }
 }
 
 // Whole Node Controller
 #[async_trait]
-pub trait NodeController: TxProcesser + Proposer + Miner + Verifier + BlockGetProcesser {
+pub trait NodeController:
+    TxProcesser + Proposer + Miner + TemplateProvider + Verifier + BlockGetProcesser
+{
     async fn run_node(&self) -> Result<(), String>;
+    async fn shutdown(&self) -> Result<(), String>;
 }
 
 #[async_trait]
 impl NodeController for Node {
     async fn run_node(&self) -> Result<(), String> {
+        self.restore_mempool().await;
+
         let _ = tokio::try_join!(
             async {
                 self.run_tx_receiver().await?;
@@ -542,9 +1207,175 @@ impl NodeController for Node {
             async {
                 self.run_chain_manager().await?;
                 Ok::<(), String>(())
+            },
+            async {
+                self.run_get_processser().await?;
+                Ok::<(), String>(())
             }
         );
 
         Ok(())
     }
+    // Stops mining and dumps the mempool so pending transactions survive a restart.
+    async fn shutdown(&self) -> Result<(), String> {
+        self.miner_pool.cancel();
+        self.save_mempool().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mini_chain::state::Account;
+
+    // A node whose broadcasts of mined blocks land in the returned receiver.
+    fn new_validator(name: &str, address: Address, validators: Vec<String>) -> (Node, Receiver<Block>) {
+        let dir = std::env::temp_dir().join(format!("mini-chain-node-{}", std::process::id()));
+        let path = dir.join(name);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(format!("{}.finalized", path.display()));
+        let (mined_block_sender, mined_block_receiver) = async_channel::unbounded();
+        let node = Node::new(
+            address,
+            mined_block_sender,
+            async_channel::unbounded().0,
+            async_channel::unbounded().0,
+            GenesisConfig::new(vec![], validators).unwrap(),
+            path.to_string_lossy().to_string(),
+        );
+        (node, mined_block_receiver)
+    }
+
+    fn new_node(name: &str) -> Node {
+        new_validator(name, Address::new(), vec![]).0
+    }
+
+    #[test]
+    fn a_store_of_another_chain_is_refused_and_kept() {
+        let dir = std::env::temp_dir().join(format!("mini-chain-node-{}", std::process::id()));
+        let path = dir.join("foreign.blocks").to_string_lossy().to_string();
+        let _ = fs::remove_file(&path);
+        let mut block = Block::default();
+        block.set_chain_id(1);
+        block.set_block_sequence(1);
+        block.set_hash(block.calculate_hash());
+        BlockStore::open(&path).unwrap().append(&block, 1).unwrap();
+        let stored_len = fs::metadata(&path).unwrap().len();
+
+        let genesis = GenesisConfig::new(vec![], vec![]).unwrap();
+        assert!(Node::load_chain(&genesis, &path).is_err());
+        assert_eq!(fs::metadata(&path).unwrap().len(), stored_len);
+    }
+
+    fn solve(mut block: Block) -> Block {
+        while !Node::verify_block_hash(block.calculate_hash(), block.bits()) {
+            block.inc_nonce();
+        }
+        block.set_hash(block.calculate_hash());
+        block
+    }
+
+    #[tokio::test]
+    async fn a_single_validator_commits_its_own_blocks() {
+        let address = Address::new();
+        let validators = vec![address.get_public_address().to_string()];
+        let (node, _mined_blocks) = new_validator("single.blocks", address, validators);
+
+        let block = solve(node.build_block().await.unwrap());
+        node.send_mined_block(block.clone()).await.unwrap();
+
+        let proc_chain = node.chain.read().await;
+        assert_eq!(proc_chain.get_leaf().unwrap(), block.hash());
+        assert_eq!(proc_chain.get_finalized(), block.hash());
+        assert!(node.stagepool.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn blocks_without_a_parent_wait_in_the_orphan_pool() {
+        let address = Address::new();
+        let validators = vec![address.get_public_address().to_string()];
+        let (builder, _mined_blocks) = new_validator("orphan-builder.blocks", address, validators.clone());
+        let (mut node, _mined_blocks) = new_validator("orphan.blocks", Address::new(), validators);
+        let (request_sender, requests) = async_channel::unbounded();
+        node.net_non_existing_block_request_sender = request_sender;
+
+        let parent = solve(builder.build_block().await.unwrap());
+        builder.send_mined_block(parent.clone()).await.unwrap();
+        let child = solve(builder.build_block().await.unwrap());
+
+        let mut forged = child.clone();
+        forged.set_hash("forged".to_string());
+        node.stage_blocks(vec![forged]).await;
+        assert!(!node.chain.read().await.is_missing_parent(&parent.hash()));
+
+        node.stage_blocks(vec![child.clone()]).await;
+        assert!(node.stagepool.read().await.is_empty());
+        assert!(node.chain.read().await.is_missing_parent(&parent.hash()));
+        assert_eq!(requests.try_recv().unwrap().hash_key, parent.hash());
+
+        // Once the parent arrives, the orphan is staged behind it.
+        node.stage_blocks(vec![parent.clone()]).await;
+        assert!(!node.chain.read().await.is_missing_parent(&parent.hash()));
+        let proc_stagepool = node.stagepool.read().await;
+        assert!(proc_stagepool.contains_key(&parent.hash()));
+        assert!(proc_stagepool.contains_key(&child.hash()));
+    }
+
+    #[tokio::test]
+    async fn waiting_on_a_transaction_that_cannot_confirm_fails() {
+        let node = new_node("wait.blocks");
+        assert!(node.wait_for_confirmations("unknown", 1).await.is_err());
+
+        let mut tx = Transaction::new(node.chain_id, "payee".to_string(), 1, 0, 0);
+        tx.sign_transaction(&Address::new());
+        assert!(node.mempool.write().await.add_transaction(tx.clone(), &Account::default()).await.is_err());
+        let result = node.wait_for_confirmations(&tx.hash, 1).await;
+        assert!(result.is_err_and(|e| e.contains("rejected")));
+    }
+
+    #[tokio::test]
+    async fn templates_are_capped_and_dropped_when_stale() {
+        let node = new_node("templates.blocks");
+        let max_templates = ChainMetaData::default().get_max_block_templates().unwrap();
+        let genesis_hash = node.chain.read().await.get_genesis_hash();
+        let template_on = |prev_hash: &str, timestamp: usize| {
+            let mut block = Block::default();
+            block.set_prev_hash(prev_hash.to_string());
+            block.set_timestamp(timestamp);
+            block
+        };
+
+        // Each of the first two templates holds a transaction picked from the mempool.
+        let mut picked = vec![];
+        for _ in 0..2 {
+            let mut tx = Transaction::new(node.chain_id, "payee".to_string(), 1, 10, 0);
+            tx.sign_transaction(&Address::new());
+            let account = Account {
+                balance: 100,
+                nonce: 0,
+            };
+            let mut proc_mempool = node.mempool.write().await;
+            proc_mempool.add_transaction(tx.clone(), &account).await.unwrap();
+            proc_mempool.pickup_transaction(1).await.unwrap();
+            picked.push(tx);
+        }
+
+        let mut ids = vec![];
+        for timestamp in 0..=max_templates {
+            let mut block = template_on(&genesis_hash, timestamp);
+            if let Some(tx) = picked.get(timestamp) {
+                block.add_transaction(tx.clone());
+            }
+            ids.push(node.store_template(&block).await.id);
+        }
+        let kept: Vec<String> = node.templates.read().await.iter().map(|(id, _)| id.clone()).collect();
+        assert_eq!(kept, ids[1..]);
+        assert_eq!(node.mempool.read().await.get_transaction_status(&picked[0].hash).await, Some(TxStatus::RECEIVED));
+        assert_eq!(node.mempool.read().await.get_transaction_status(&picked[1].hash).await, Some(TxStatus::PROCESSING));
+
+        node.store_template(&template_on("newer tip", 0)).await;
+        assert_eq!(node.templates.read().await.len(), 1);
+        assert!(node.submit_block(ids[1].clone(), 0).await.is_err());
+        assert_eq!(node.mempool.read().await.get_transaction_status(&picked[1].hash).await, Some(TxStatus::RECEIVED));
+    }
 }
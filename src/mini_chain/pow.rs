@@ -0,0 +1,173 @@
+use primitive_types::{U256, U512};
+
+// Easiest target a block may have, in compact form: 0x7fffff * 256^29, roughly 2^255.
+pub const POW_LIMIT_BITS: u32 = 0x207fffff;
+
+pub fn pow_limit() -> U256 {
+    target_from_bits(POW_LIMIT_BITS)
+}
+
+// Compact "bits" form: the high byte is the length of the target in bytes, the low three bytes its
+// most significant digits. Targets with the sign bit set or that do not fit in 256 bits decode to zero.
+pub fn target_from_bits(bits: u32) -> U256 {
+    let exponent = (bits >> 24) as usize;
+    let mantissa = bits & 0x007fffff;
+    if bits & 0x00800000 != 0 || mantissa == 0 {
+        return U256::zero();
+    }
+
+    if exponent <= 3 {
+        return U256::from(mantissa >> (8 * (3 - exponent)));
+    }
+    let shift = 8 * (exponent - 3);
+    if shift + (32 - mantissa.leading_zeros() as usize) > 256 {
+        return U256::zero();
+    }
+    U256::from(mantissa) << shift
+}
+
+pub fn bits_from_target(target: U256) -> u32 {
+    let mut size = target.bits().div_ceil(8);
+    let mut mantissa = if size <= 3 {
+        target.low_u32() << (8 * (3 - size))
+    } else {
+        (target >> (8 * (size - 3))).low_u32()
+    };
+
+    // The mantissa is signed, so keep its top bit clear by moving a byte into the exponent.
+    if mantissa & 0x00800000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+    mantissa | ((size as u32) << 24)
+}
+
+// How many times harder than the easiest allowed target.
+pub fn difficulty_from_target(target: U256) -> U256 {
+    if target.is_zero() {
+        return U256::MAX;
+    }
+    pow_limit() / target
+}
+
+pub fn target_from_difficulty(difficulty: U256) -> U256 {
+    if difficulty.is_zero() {
+        return pow_limit();
+    }
+    pow_limit() / difficulty
+}
+
+// Expected number of hashes to find one at or below `target`: 2^256 / (target + 1). A zero target,
+// which invalid bits decode to, would take 2^256 hashes and saturates to the maximum.
+pub fn work_from_target(target: U256) -> U256 {
+    if target.is_zero() {
+        return U256::MAX;
+    }
+    if target == U256::MAX {
+        return U256::one();
+    }
+    (!target / (target + 1)) + 1
+}
+
+pub fn expected_hashes(bits: u32) -> U256 {
+    work_from_target(target_from_bits(bits))
+}
+
+// Scales `bits` by how long a retarget window took against how long it should have, at most 4x either
+// way and never easier than the limit. The timespan is clamped before it is scaled to milliseconds and
+// the product is taken in 512 bits, so neither a wild timestamp nor a target near the limit overflows.
+pub fn retarget(bits: u32, timespan_secs: usize, expected_ms: usize) -> Result<u32, String> {
+    if expected_ms == 0 {
+        return Err("Retarget window has no expected duration".to_string());
+    }
+    let max_ms = expected_ms.saturating_mul(4);
+    let actual_ms = timespan_secs
+        .min(max_ms / 1000 + 1)
+        .saturating_mul(1000)
+        .clamp(expected_ms / 4, max_ms);
+
+    let scaled = target_from_bits(bits).full_mul(U256::from(actual_ms)) / U512::from(expected_ms);
+    let target = U256::try_from(scaled).unwrap_or(U256::MAX);
+    Ok(bits_from_target(target.clamp(U256::one(), pow_limit())))
+}
+
+pub fn hash_meets_target(hash: &str, target: U256) -> bool {
+    match hex::decode(hash) {
+        Ok(bytes) if bytes.len() == 32 => U256::from_big_endian(&bytes) <= target,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPECTED_MS: usize = 10 * 2500;
+
+    fn mid_bits() -> u32 {
+        bits_from_target(U256::from(0x010000) << 160)
+    }
+
+    #[test]
+    fn bits_round_trip_through_targets() {
+        for bits in [POW_LIMIT_BITS, 0x1d00ffff, 0x1b0404cb, 0x04123456, 0x03123456, 0x02008000] {
+            assert_eq!(bits_from_target(target_from_bits(bits)), bits, "{:08x}", bits);
+        }
+        assert_eq!(target_from_bits(0x1b0404cb), U256::from(0x0404cb) << (8 * (0x1b - 3)));
+        assert_eq!(target_from_bits(0x01123456), U256::from(0x12));
+    }
+
+    #[test]
+    fn negative_or_oversized_bits_decode_to_zero() {
+        assert!(target_from_bits(0x04923456).is_zero());
+        assert!(target_from_bits(0x21010000).is_zero());
+        assert!(target_from_bits(0x1d000000).is_zero());
+        assert_eq!(bits_from_target(U256::from(0x80)), 0x02008000);
+    }
+
+    #[test]
+    fn harder_targets_carry_more_work() {
+        assert_eq!(work_from_target(U256::MAX), U256::one());
+        assert_eq!(work_from_target(pow_limit()), U256::from(2));
+        assert!(expected_hashes(mid_bits()) > expected_hashes(POW_LIMIT_BITS));
+        let difficulty = U256::from(128);
+        assert_eq!(difficulty_from_target(target_from_difficulty(difficulty)), difficulty);
+    }
+
+    #[test]
+    fn a_zero_target_saturates_the_work() {
+        assert_eq!(work_from_target(U256::zero()), U256::MAX);
+        assert_eq!(expected_hashes(0x01800000), U256::MAX);
+        assert!(work_from_target(U256::one()) < U256::MAX);
+    }
+
+    #[test]
+    fn retarget_keeps_bits_on_schedule() {
+        assert_eq!(retarget(mid_bits(), EXPECTED_MS / 1000, EXPECTED_MS), Ok(mid_bits()));
+    }
+
+    #[test]
+    fn retarget_clamps_to_four_times_either_way() {
+        let target = target_from_bits(mid_bits());
+
+        let slow = retarget(mid_bits(), usize::MAX, EXPECTED_MS).unwrap();
+        assert_eq!(target_from_bits(slow), target * 4);
+
+        let fast = retarget(mid_bits(), 0, EXPECTED_MS).unwrap();
+        assert_eq!(target_from_bits(fast), target / 4);
+    }
+
+    #[test]
+    fn retarget_saturates_at_the_limit() {
+        assert_eq!(retarget(POW_LIMIT_BITS, usize::MAX, EXPECTED_MS), Ok(POW_LIMIT_BITS));
+        assert_eq!(retarget(POW_LIMIT_BITS, usize::MAX, usize::MAX), Ok(POW_LIMIT_BITS));
+
+        let near_limit = bits_from_target(pow_limit() / 2);
+        assert_eq!(retarget(near_limit, usize::MAX, EXPECTED_MS), Ok(POW_LIMIT_BITS));
+    }
+
+    #[test]
+    fn retarget_rejects_an_empty_window() {
+        assert!(retarget(mid_bits(), 10, 0).is_err());
+    }
+}
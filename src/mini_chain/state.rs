@@ -0,0 +1,177 @@
+use super::{block::Block, transaction::Transaction};
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Account {
+    pub balance: usize,
+    pub nonce: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WorldState {
+    accounts: HashMap<String, Account>,
+}
+
+impl WorldState {
+    pub fn new(allocations: Vec<(String, usize)>) -> Self {
+        let mut state = Self::default();
+        for (addr, amount) in allocations {
+            state.credit(&addr, amount);
+        }
+        state
+    }
+}
+
+pub trait WorldStateOperation {
+    fn get_account(&self, addr: &str) -> Account;
+    fn get_balance(&self, addr: &str) -> usize;
+    fn credit(&mut self, addr: &str, amount: usize);
+    fn apply_transaction(&mut self, tx: &Transaction) -> Result<(), String>;
+    fn apply_block(&mut self, block: &Block) -> Result<(), String>;
+    fn revert_transaction(&mut self, tx: &Transaction) -> Result<(), String>;
+    fn revert_block(&mut self, block: &Block) -> Result<(), String>;
+}
+
+impl WorldStateOperation for WorldState {
+    fn get_account(&self, addr: &str) -> Account {
+        self.accounts.get(addr).cloned().unwrap_or_default()
+    }
+
+    fn get_balance(&self, addr: &str) -> usize {
+        self.get_account(addr).balance
+    }
+
+    fn credit(&mut self, addr: &str, amount: usize) {
+        self.accounts.entry(addr.to_string()).or_default().balance += amount;
+    }
+
+    fn apply_transaction(&mut self, tx: &Transaction) -> Result<(), String> {
+        if tx.is_coinbase() {
+            self.credit(&tx.payload.addr, tx.payload.amount);
+            return Ok(());
+        }
+
+        let sender = self.accounts.entry(tx.signer.clone()).or_default();
+        if tx.nonce != sender.nonce {
+            return Err(format!(
+                "Invalid nonce: {} expects {}, got {}",
+                tx.signer, sender.nonce, tx.nonce
+            ));
+        }
+        let cost = tx.payload.amount.saturating_add(tx.fee);
+        if sender.balance < cost {
+            return Err(format!(
+                "Insufficient balance: {} has {}, needs {}",
+                tx.signer, sender.balance, cost
+            ));
+        }
+        sender.balance -= cost;
+        sender.nonce += 1;
+
+        self.credit(&tx.payload.addr, tx.payload.amount);
+        Ok(())
+    }
+
+    // Applies all transactions of a block, leaving the state untouched if any of them fails.
+    fn apply_block(&mut self, block: &Block) -> Result<(), String> {
+        let mut next_state = self.clone();
+        for (index, tx) in block.transactions().iter().enumerate() {
+            if tx.is_coinbase() && index != 0 {
+                return Err(format!("Coinbase {} is not the first transaction", tx.hash));
+            }
+            next_state.apply_transaction(tx)?;
+        }
+        *self = next_state;
+        Ok(())
+    }
+
+    // Undoes a transfer previously applied on top of this state.
+    fn revert_transaction(&mut self, tx: &Transaction) -> Result<(), String> {
+        let recipient = self.accounts.entry(tx.payload.addr.clone()).or_default();
+        if recipient.balance < tx.payload.amount {
+            return Err(format!(
+                "Cannot revert transfer: {} has {}, needs {}",
+                tx.payload.addr, recipient.balance, tx.payload.amount
+            ));
+        }
+        recipient.balance -= tx.payload.amount;
+        if tx.is_coinbase() {
+            return Ok(());
+        }
+
+        let sender = self.accounts.entry(tx.signer.clone()).or_default();
+        if sender.nonce != tx.nonce + 1 {
+            return Err(format!(
+                "Cannot revert nonce: {} is at {}, transaction has {}",
+                tx.signer, sender.nonce, tx.nonce
+            ));
+        }
+        sender.nonce -= 1;
+        sender.balance += tx.payload.amount + tx.fee;
+        Ok(())
+    }
+
+    // Undoes all transactions of a block in reverse order, leaving the state untouched if any of them fails.
+    fn revert_block(&mut self, block: &Block) -> Result<(), String> {
+        let mut prev_state = self.clone();
+        for tx in block.transactions().iter().rev() {
+            prev_state.revert_transaction(tx)?;
+        }
+        *self = prev_state;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mini_chain::{address::Address, block::BlockConfigurer};
+
+    fn transfer(from: &Address, to: &str, amount: usize, fee: usize, nonce: usize) -> Transaction {
+        let mut tx = Transaction::new(0, to.to_string(), amount, fee, nonce);
+        tx.sign_transaction(from);
+        tx
+    }
+
+    #[test]
+    fn reverting_a_block_restores_the_previous_state() {
+        let sender = Address::new();
+        let sender_addr = sender.get_public_address().to_string();
+        let mut state = WorldState::new(vec![(sender_addr.clone(), 100)]);
+        let before = state.clone();
+
+        let mut block = Block::default();
+        block.add_transaction(Transaction::new_coinbase(0, "miner".to_string(), 50, 1));
+        block.add_transaction(transfer(&sender, "payee", 30, 2, 0));
+        block.add_transaction(transfer(&sender, "payee", 10, 1, 1));
+        state.apply_block(&block).unwrap();
+        assert_eq!(state.get_account(&sender_addr), Account { balance: 57, nonce: 2 });
+        assert_eq!(state.get_balance("payee"), 40);
+        assert_eq!(state.get_balance("miner"), 50);
+
+        state.revert_block(&block).unwrap();
+        for addr in [sender_addr.as_str(), "payee", "miner"] {
+            assert_eq!(state.get_account(addr), before.get_account(addr), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn failed_blocks_leave_the_state_untouched() {
+        let sender = Address::new();
+        let sender_addr = sender.get_public_address().to_string();
+        let mut state = WorldState::new(vec![(sender_addr.clone(), 100)]);
+
+        let mut block = Block::default();
+        block.add_transaction(transfer(&sender, "payee", 30, 0, 0));
+        block.add_transaction(transfer(&sender, "payee", 30, 0, 5));
+        assert!(state.apply_block(&block).is_err());
+        assert_eq!(state.get_account(&sender_addr), Account { balance: 100, nonce: 0 });
+        assert_eq!(state.get_balance("payee"), 0);
+
+        // A block that was never applied cannot be reverted either.
+        let mut unapplied = Block::default();
+        unapplied.add_transaction(transfer(&sender, "payee", 30, 0, 0));
+        assert!(state.revert_block(&unapplied).is_err());
+        assert_eq!(state.get_account(&sender_addr), Account { balance: 100, nonce: 0 });
+    }
+}
@@ -0,0 +1,329 @@
+use super::{
+    block::Block,
+    codec::{self, Decode, Encode, Reader},
+};
+use sha3::{Digest, Sha3_256};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+// Every record is a big-endian u32 payload length, the SHA3 checksum of the payload and the payload.
+const LENGTH_SIZE: usize = 4;
+const CHECKSUM_SIZE: usize = 32;
+
+#[derive(Debug, PartialEq, Clone)]
+struct StoredBlock {
+    height: u64,
+    block: Block,
+}
+
+impl Encode for StoredBlock {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.height.encode(buf);
+        self.block.encode(buf);
+    }
+}
+
+impl Decode for StoredBlock {
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Self {
+            height: u64::decode(reader)?,
+            block: Block::decode(reader)?,
+        })
+    }
+}
+
+fn encode_record(stored: &StoredBlock) -> Vec<u8> {
+    let payload = codec::to_bytes(stored);
+    let mut record = Vec::with_capacity(LENGTH_SIZE + CHECKSUM_SIZE + payload.len());
+    record.extend((payload.len() as u32).to_be_bytes());
+    record.extend(Sha3_256::digest(&payload));
+    record.extend(payload);
+    record
+}
+
+// Decodes the record at the start of `bytes`, returning it with its length.
+// None marks the end of the intact records: a torn write or a corrupted tail.
+fn decode_record(bytes: &[u8]) -> Option<(StoredBlock, usize)> {
+    let header_size = LENGTH_SIZE + CHECKSUM_SIZE;
+    if bytes.len() < header_size {
+        return None;
+    }
+    let payload_size = u32::from_be_bytes(bytes[..LENGTH_SIZE].try_into().unwrap()) as usize;
+    let payload = bytes.get(header_size..header_size + payload_size)?;
+    if Sha3_256::digest(payload).as_slice() != &bytes[LENGTH_SIZE..header_size] {
+        return None;
+    }
+    let stored = codec::from_bytes::<StoredBlock>(payload).ok()?;
+    Some((stored, header_size + payload_size))
+}
+
+// Append-only block file. Blocks are written once, in the order the chain accepted them, and
+// indexed by hash and by height. Every append is flushed to disk before it is acknowledged.
+#[derive(Debug)]
+pub struct BlockStore {
+    path: PathBuf,
+    file: File,
+    // Offset of each block's record in the file.
+    by_hash: HashMap<String, u64>,
+    // Hashes of the blocks stored at each height, across all branches.
+    by_height: HashMap<u64, Vec<String>>,
+    // Hashes in append order.
+    order: Vec<String>,
+    // Last finalized block, kept in a file of its own next to the blocks.
+    finalized: Option<String>,
+}
+
+impl BlockStore {
+    // Opens or creates the store, indexing its records. A tail left by an interrupted write is cut off.
+    pub fn open(path: &str) -> Result<Self, String> {
+        let path = PathBuf::from(path);
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)
+            .map_err(|e| e.to_string())?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+
+        let finalized = fs::read_to_string(BlockStore::finalized_path(&path))
+            .ok()
+            .map(|hash| hash.trim().to_string())
+            .filter(|hash| !hash.is_empty());
+        let mut store = Self {
+            path,
+            file,
+            by_hash: HashMap::new(),
+            by_height: HashMap::new(),
+            order: vec![],
+            finalized,
+        };
+
+        let mut offset = 0;
+        while let Some((stored, record_size)) = decode_record(&bytes[offset..]) {
+            store.index(&stored, offset as u64);
+            offset += record_size;
+        }
+        if offset < bytes.len() {
+            println!(
+                "Discarding {} bytes of damaged records at the end of {}",
+                bytes.len() - offset,
+                store.path.display()
+            );
+            store.file.set_len(offset as u64).map_err(|e| e.to_string())?;
+            store.file.sync_all().map_err(|e| e.to_string())?;
+        }
+        Ok(store)
+    }
+
+    fn finalized_path(path: &Path) -> PathBuf {
+        PathBuf::from(format!("{}.finalized", path.display()))
+    }
+
+    fn index(&mut self, stored: &StoredBlock, offset: u64) {
+        let hash = stored.block.hash();
+        self.by_hash.insert(hash.clone(), offset);
+        self.by_height
+            .entry(stored.height)
+            .or_default()
+            .push(hash.clone());
+        self.order.push(hash);
+    }
+
+    fn read_record(&self, offset: u64) -> Result<StoredBlock, String> {
+        let mut file = File::open(&self.path).map_err(|e| e.to_string())?;
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|e| e.to_string())?;
+
+        let mut header = [0u8; LENGTH_SIZE + CHECKSUM_SIZE];
+        file.read_exact(&mut header).map_err(|e| e.to_string())?;
+        let payload_size = u32::from_be_bytes(header[..LENGTH_SIZE].try_into().unwrap()) as usize;
+
+        let mut record = header.to_vec();
+        record.resize(header.len() + payload_size, 0);
+        file.read_exact(&mut record[header.len()..])
+            .map_err(|e| e.to_string())?;
+
+        decode_record(&record)
+            .map(|(stored, _)| stored)
+            .ok_or(format!("Damaged block record at offset {}", offset))
+    }
+
+    // Rewrites the store with the given blocks only. The new file replaces the old one in a single rename.
+    pub fn rewrite(&mut self, blocks: &[(Block, u64)]) -> Result<(), String> {
+        let tmp_path = self.path.with_extension("tmp");
+        {
+            let mut tmp_file = File::create(&tmp_path).map_err(|e| e.to_string())?;
+            for (block, height) in blocks {
+                let stored = StoredBlock {
+                    height: *height,
+                    block: block.clone(),
+                };
+                tmp_file
+                    .write_all(&encode_record(&stored))
+                    .map_err(|e| e.to_string())?;
+            }
+            tmp_file.sync_all().map_err(|e| e.to_string())?;
+        }
+        fs::rename(&tmp_path, &self.path).map_err(|e| e.to_string())?;
+
+        *self = BlockStore::open(self.path.to_str().unwrap_or_default())?;
+        Ok(())
+    }
+}
+
+pub trait BlockStoreOperation {
+    fn append(&mut self, block: &Block, height: u64) -> Result<(), String>;
+    fn contains_block(&self, hash: &str) -> bool;
+    fn get_block(&self, hash: &str) -> Result<Option<Block>, String>;
+    fn get_hashes_at_height(&self, height: u64) -> Vec<String>;
+    fn get_blocks(&self) -> Result<Vec<(Block, u64)>, String>;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool;
+    fn set_finalized(&mut self, hash: &str) -> Result<(), String>;
+    fn get_finalized(&self) -> Option<String>;
+}
+
+impl BlockStoreOperation for BlockStore {
+    fn append(&mut self, block: &Block, height: u64) -> Result<(), String> {
+        if self.by_hash.contains_key(&block.hash()) {
+            return Ok(());
+        }
+
+        let stored = StoredBlock {
+            height,
+            block: block.clone(),
+        };
+        let offset = self.file.metadata().map_err(|e| e.to_string())?.len();
+        let written = self
+            .file
+            .write_all(&encode_record(&stored))
+            .and_then(|_| self.file.sync_data());
+        if let Err(e) = written {
+            // Never leave a partial record in front of the next append.
+            let _ = self.file.set_len(offset);
+            return Err(e.to_string());
+        }
+
+        self.index(&stored, offset);
+        Ok(())
+    }
+
+    fn contains_block(&self, hash: &str) -> bool {
+        self.by_hash.contains_key(hash)
+    }
+
+    fn get_block(&self, hash: &str) -> Result<Option<Block>, String> {
+        match self.by_hash.get(hash) {
+            Some(offset) => Ok(Some(self.read_record(*offset)?.block)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_hashes_at_height(&self, height: u64) -> Vec<String> {
+        self.by_height.get(&height).cloned().unwrap_or_default()
+    }
+
+    // Every stored block with its height, in the order they were appended.
+    fn get_blocks(&self) -> Result<Vec<(Block, u64)>, String> {
+        let bytes = fs::read(&self.path).map_err(|e| e.to_string())?;
+        let mut blocks = vec![];
+        let mut offset = 0;
+        while let Some((stored, record_size)) = decode_record(&bytes[offset..]) {
+            blocks.push((stored.block, stored.height));
+            offset += record_size;
+        }
+        Ok(blocks)
+    }
+
+    fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    // Replaces the recorded finalized block in a single rename.
+    fn set_finalized(&mut self, hash: &str) -> Result<(), String> {
+        let path = BlockStore::finalized_path(&self.path);
+        let tmp_path = format!("{}.tmp", path.display());
+        {
+            let mut tmp_file = File::create(&tmp_path).map_err(|e| e.to_string())?;
+            tmp_file
+                .write_all(hash.as_bytes())
+                .map_err(|e| e.to_string())?;
+            tmp_file.sync_all().map_err(|e| e.to_string())?;
+        }
+        fs::rename(&tmp_path, &path).map_err(|e| e.to_string())?;
+        self.finalized = Some(hash.to_string());
+        Ok(())
+    }
+
+    fn get_finalized(&self) -> Option<String> {
+        self.finalized.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mini_chain::block::BlockConfigurer;
+
+    fn temp_store_path(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("mini-chain-store-{}", std::process::id()));
+        let path = dir.join(name);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(BlockStore::finalized_path(&path));
+        path.to_string_lossy().to_string()
+    }
+
+    fn block(height: u64) -> Block {
+        let mut block = Block::default();
+        block.set_block_sequence(height);
+        block.set_hash(block.calculate_hash());
+        block
+    }
+
+    #[test]
+    fn blocks_and_finality_survive_reopening() {
+        let path = temp_store_path("reopen.blocks");
+        let blocks = [block(1), block(2)];
+        {
+            let mut store = BlockStore::open(&path).unwrap();
+            for (height, block) in blocks.iter().enumerate() {
+                store.append(block, height as u64 + 1).unwrap();
+            }
+            assert_eq!(store.get_finalized(), None);
+            store.set_finalized(&blocks[0].hash()).unwrap();
+        }
+
+        let store = BlockStore::open(&path).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get_hashes_at_height(2), vec![blocks[1].hash()]);
+        assert_eq!(store.get_finalized(), Some(blocks[0].hash()));
+    }
+
+    #[test]
+    fn damaged_tail_is_cut_off() {
+        let path = temp_store_path("damaged.blocks");
+        {
+            let mut store = BlockStore::open(&path).unwrap();
+            store.append(&block(1), 1).unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0, 0, 1]).unwrap();
+
+        let store = BlockStore::open(&path).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get_blocks().unwrap().len(), 1);
+    }
+}
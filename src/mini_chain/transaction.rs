@@ -1,5 +1,9 @@
-use std::fmt;
+use super::{
+    address::{self, Address},
+    codec::{self, Decode, Encode, Reader},
+};
 use sha3::{Digest, Sha3_256};
+use std::{cmp::Ordering, fmt};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, PartialEq, Clone)]
@@ -16,59 +20,184 @@ impl fmt::Display for TxPayload {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Transaction {
+    // The chain the transaction was signed for, so it cannot be replayed on another one.
+    pub chain_id: u64,
     pub timestamp: usize,
     pub nonce: usize,
     pub payload: TxPayload,
+    // Paid by the signer on top of the amount and collected by the coinbase of the including block.
+    pub fee: usize,
     pub signer: String,
     pub signature: String,
     pub hash: String,
 }
 
 impl Transaction {
-    pub fn new(to_addr: String, amount: usize) -> Self {
+    pub fn new(chain_id: u64, to_addr: String, amount: usize, fee: usize, nonce: usize) -> Self {
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs() as usize;
-        Self {
-            timestamp: timestamp,
-            nonce: 0,
+        let mut tx = Self {
+            chain_id,
+            timestamp,
+            nonce,
             payload: TxPayload {
                 addr: to_addr.clone(),
-                amount: amount,
+                amount,
             },
+            fee,
             signer: String::new(),
             signature: String::new(),
             hash: String::new(),
-        }
+        };
+        tx.hash = tx.calculate_hash();
+        tx
     }
 
-    pub fn sign_transaction(&mut self, addr: String, signature: String) {
-        self.signer = addr.clone();
-        self.signature = signature.clone();
+    // Mints the block reward to the builder. A coinbase has no signer, and its nonce is the height of
+    // the block so that coinbases of different blocks never share a hash.
+    pub fn new_coinbase(chain_id: u64, builder: String, amount: usize, height: u64) -> Self {
+        Self::new(chain_id, builder, amount, 0, height as usize)
     }
 
-    pub fn calculate_hash(tx: Transaction) -> String {
-        let mut hasher = Sha3_256::new();
+    pub fn is_coinbase(&self) -> bool {
+        self.signer.is_empty()
+    }
+
+    // What the signer pays for the transaction: the amount and the fee.
+    pub fn cost(&self) -> usize {
+        self.payload.amount.saturating_add(self.fee)
+    }
+
+    // Encoded size in bytes, the unit fee rates are expressed in.
+    pub fn size(&self) -> usize {
+        codec::to_bytes(self).len()
+    }
+
+    // Orders transactions by fee per byte, comparing fee_a * size_b with fee_b * size_a to stay exact.
+    pub fn cmp_fee_rate(&self, other: &Transaction) -> Ordering {
+        let lhs = self.fee as u128 * other.size() as u128;
+        let rhs = other.fee as u128 * self.size() as u128;
+        lhs.cmp(&rhs)
+    }
 
-        let data = format!(
-            "{}{}{}{}{}",
-            tx.timestamp, tx.nonce, tx.payload, tx.signer, tx.signature
-        );
+    // Bytes covered by the signature: every field except the signature and hash themselves.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![codec::CODEC_VERSION];
+        self.chain_id.encode(&mut bytes);
+        self.timestamp.encode(&mut bytes);
+        self.nonce.encode(&mut bytes);
+        self.payload.encode(&mut bytes);
+        self.fee.encode(&mut bytes);
+        self.signer.encode(&mut bytes);
+        bytes
+    }
 
-        hasher.update(data);
+    pub fn sign_transaction(&mut self, addr: &Address) {
+        self.signer = addr.get_public_address().to_string();
+        self.signature = addr.sign(&self.signing_bytes());
+        self.hash = self.calculate_hash();
+    }
+
+    pub fn verify_signature(&self) -> Result<(), String> {
+        address::verify_signature(&self.signer, &self.signing_bytes(), &self.signature)
+    }
+
+    // Bytes covered by the hash: the signed bytes followed by the signature.
+    pub fn hashing_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.signing_bytes();
+        self.signature.encode(&mut bytes);
+        bytes
+    }
+
+    pub fn calculate_hash(&self) -> String {
+        let mut hasher = Sha3_256::new();
+        hasher.update(self.hashing_bytes());
 
         let hash = format!("{:x}", hasher.finalize());
         hash
     }
+
+    pub fn verify_hash(&self) -> Result<(), String> {
+        if self.hash != self.calculate_hash() {
+            return Err(format!("Transaction hash mismatch: {}", self.hash));
+        }
+        Ok(())
+    }
+}
+
+impl Encode for TxPayload {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.addr.encode(buf);
+        self.amount.encode(buf);
+    }
+}
+
+impl Decode for TxPayload {
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Self {
+            addr: String::decode(reader)?,
+            amount: usize::decode(reader)?,
+        })
+    }
+}
+
+impl Encode for Transaction {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.chain_id.encode(buf);
+        self.timestamp.encode(buf);
+        self.nonce.encode(buf);
+        self.payload.encode(buf);
+        self.fee.encode(buf);
+        self.signer.encode(buf);
+        self.signature.encode(buf);
+        self.hash.encode(buf);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Self {
+            chain_id: u64::decode(reader)?,
+            timestamp: usize::decode(reader)?,
+            nonce: usize::decode(reader)?,
+            payload: TxPayload::decode(reader)?,
+            fee: usize::decode(reader)?,
+            signer: String::decode(reader)?,
+            signature: String::decode(reader)?,
+            hash: String::decode(reader)?,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TxRejectReason {
+    Coinbase,
+    InvalidHash,
+    InvalidSignature,
+    StaleNonce,
+    Underpriced,
+    FeeTooLow,
+    PoolFull,
+    Expired,
+    Evicted,
+    Replaced,
+    // The transaction it would replace is already part of a block template.
+    Processing,
+    // Another transaction with the same signer and nonce was committed.
+    Conflicted,
+    // The amount and fee, on top of the signer's pooled transactions, exceed the signer's balance.
+    InsufficientFunds,
 }
 
+// RECEIVED transactions wait in the mempool, PROCESSING ones are part of a block template.
 #[derive(Debug, PartialEq, Clone)]
 pub enum TxStatus {
     RECEIVED,
     PROCESSING,
-    APPROVED,
-    REJECTED,
+    APPROVED { block_hash: String, height: u64 },
+    REJECTED(TxRejectReason),
 }
 
 #[derive(Debug, PartialEq)]
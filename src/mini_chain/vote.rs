@@ -0,0 +1,453 @@
+use super::{
+    address::{self, Address},
+    codec::{self, Decode, Encode, Reader},
+};
+use std::collections::{BTreeMap, HashMap};
+
+// A validator's vote on a block at a height, signed with the voter's key.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BlockVerifyTx {
+    pub chain_id: u64,
+    pub block_hash: String,
+    pub height: u64,
+    pub round: u64,
+    pub verified: bool,
+    pub voter: String,
+    pub signature: String,
+}
+
+impl BlockVerifyTx {
+    pub fn new(
+        chain_id: u64,
+        block_hash: String,
+        height: u64,
+        round: u64,
+        verified: bool,
+        voter: &Address,
+    ) -> Self {
+        let mut vote = Self {
+            chain_id,
+            block_hash,
+            height,
+            round,
+            verified,
+            voter: voter.get_public_address().to_string(),
+            signature: String::new(),
+        };
+        vote.signature = voter.sign(&vote.signing_bytes());
+        vote
+    }
+
+    // Bytes covered by the signature: every field except the signature itself.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![codec::CODEC_VERSION];
+        self.chain_id.encode(&mut bytes);
+        self.block_hash.encode(&mut bytes);
+        self.height.encode(&mut bytes);
+        self.round.encode(&mut bytes);
+        self.verified.encode(&mut bytes);
+        self.voter.encode(&mut bytes);
+        bytes
+    }
+
+    pub fn verify_signature(&self) -> Result<(), String> {
+        address::verify_signature(&self.voter, &self.signing_bytes(), &self.signature)
+    }
+
+    // Two approvals signed by the same validator for different blocks at the same height and round.
+    pub fn conflicts_with(&self, other: &BlockVerifyTx) -> bool {
+        self.voter == other.voter
+            && self.height == other.height
+            && self.round == other.round
+            && self.verified
+            && other.verified
+            && self.block_hash != other.block_hash
+    }
+}
+
+impl Encode for BlockVerifyTx {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.chain_id.encode(buf);
+        self.block_hash.encode(buf);
+        self.height.encode(buf);
+        self.round.encode(buf);
+        self.verified.encode(buf);
+        self.voter.encode(buf);
+        self.signature.encode(buf);
+    }
+}
+
+impl Decode for BlockVerifyTx {
+    fn decode(reader: &mut Reader) -> Result<Self, String> {
+        Ok(Self {
+            chain_id: u64::decode(reader)?,
+            block_hash: String::decode(reader)?,
+            height: u64::decode(reader)?,
+            round: u64::decode(reader)?,
+            verified: bool::decode(reader)?,
+            voter: String::decode(reader)?,
+            signature: String::decode(reader)?,
+        })
+    }
+}
+
+// Proof that a validator equivocated: both votes carry its signature.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Equivocation {
+    pub first: BlockVerifyTx,
+    pub second: BlockVerifyTx,
+}
+
+// Approvals above the finalized height, at most one per validator, height and round.
+//
+// Rounds are shared by every node: a round only gives way to the next once the approvals cast in it show
+// that no block can reach a quorum in it anymore, counting the validators that have not voted yet as
+// votes for the leading block. Two blocks never both gather a quorum in one round,
+// since any two quorums share an honest validator, and a block that gathered one keeps its round open
+// for good, so no honest validator moves on to approve another block at that height.
+#[derive(Debug, Clone, Default)]
+pub struct VoteBook {
+    validators: Vec<String>,
+    quorum: usize,
+    // The approval of each validator, by height and round.
+    heights: BTreeMap<u64, BTreeMap<u64, HashMap<String, BlockVerifyTx>>>,
+    evidence: Vec<Equivocation>,
+}
+
+impl VoteBook {
+    pub fn new(validators: Vec<String>, quorum: usize) -> Self {
+        Self {
+            validators,
+            quorum,
+            heights: BTreeMap::new(),
+            evidence: vec![],
+        }
+    }
+
+    fn round_votes(&self, height: u64, round: u64) -> Option<&HashMap<String, BlockVerifyTx>> {
+        self.heights.get(&height)?.get(&round)
+    }
+
+    fn count_in_round(&self, block_hash: &str, height: u64, round: u64) -> usize {
+        self.round_votes(height, round).map_or(0, |votes| {
+            votes
+                .values()
+                .filter(|vote| vote.block_hash == block_hash)
+                .count()
+        })
+    }
+
+    fn has_quorum_in_round(&self, height: u64, round: u64) -> bool {
+        self.round_votes(height, round).is_some_and(|votes| {
+            votes
+                .values()
+                .any(|vote| self.count_in_round(&vote.block_hash, height, round) >= self.quorum)
+        })
+    }
+
+    // No block can reach a quorum in the round anymore, even if every validator that has not voted yet
+    // approves the leading block. A fully voted round without a quorum is over.
+    fn is_round_over(&self, height: u64, round: u64) -> bool {
+        let votes = self.round_votes(height, round);
+        let voted = votes.map_or(0, |votes| votes.len());
+        let leading = votes.map_or(0, |votes| {
+            votes
+                .values()
+                .map(|vote| self.count_in_round(&vote.block_hash, height, round))
+                .max()
+                .unwrap_or_default()
+        });
+        leading + (self.validators.len() - voted) < self.quorum
+    }
+
+    // A validator that approved a block in a round that reached a quorum and then another block in a
+    // later round moved on from a round that could never be over.
+    fn find_skipped_round(&self, voter: &str, height: u64) -> Option<Equivocation> {
+        let rounds = self.heights.get(&height)?;
+        let cast: Vec<&BlockVerifyTx> = rounds.values().filter_map(|votes| votes.get(voter)).collect();
+        for (index, first) in cast.iter().enumerate() {
+            if !self.has_quorum_in_round(height, first.round) {
+                continue;
+            }
+            if let Some(second) = cast[index + 1..]
+                .iter()
+                .find(|second| second.block_hash != first.block_hash)
+            {
+                return Some(Equivocation {
+                    first: (*first).clone(),
+                    second: (*second).clone(),
+                });
+            }
+        }
+        None
+    }
+
+    fn record_evidence(&mut self, equivocation: Equivocation) -> String {
+        let message = format!(
+            "Validator {} equivocated at height {}: {} in round {} and {} in round {}",
+            equivocation.first.voter,
+            equivocation.first.height,
+            equivocation.first.block_hash,
+            equivocation.first.round,
+            equivocation.second.block_hash,
+            equivocation.second.round
+        );
+        if !self.evidence.contains(&equivocation) {
+            self.evidence.push(equivocation);
+        }
+        message
+    }
+}
+
+pub trait VoteBookOperation {
+    fn add_vote(&mut self, vote: &BlockVerifyTx) -> Result<bool, String>;
+    fn current_round(&self, height: u64) -> u64;
+    fn has_voted(&self, voter: &str, height: u64, round: u64) -> bool;
+    fn has_quorum(&self, block_hash: &str, height: u64) -> bool;
+    fn prune(&mut self, finalized_height: u64);
+    fn get_evidence(&self) -> Vec<Equivocation>;
+}
+
+impl VoteBookOperation for VoteBook {
+    // Returns whether the vote counts towards a block. Rejections say nothing about the block and are
+    // not counted. A second approval for another block at the same height and round is refused, and so
+    // is an approval in a later round than one that reached a quorum; both votes are kept as evidence
+    // against their voter.
+    fn add_vote(&mut self, vote: &BlockVerifyTx) -> Result<bool, String> {
+        if !self.validators.contains(&vote.voter) {
+            return Err(format!("{} is not a validator", vote.voter));
+        }
+        vote.verify_signature()?;
+        if !vote.verified {
+            return Ok(false);
+        }
+
+        let votes = self
+            .heights
+            .entry(vote.height)
+            .or_default()
+            .entry(vote.round)
+            .or_default();
+        if let Some(earlier) = votes.get(&vote.voter) {
+            if !earlier.conflicts_with(vote) {
+                return Ok(false);
+            }
+            let equivocation = Equivocation {
+                first: earlier.clone(),
+                second: vote.clone(),
+            };
+            return Err(self.record_evidence(equivocation));
+        }
+        votes.insert(vote.voter.clone(), vote.clone());
+
+        // A new quorum may expose validators that already moved on from this round.
+        let voters: Vec<String> = match self.has_quorum_in_round(vote.height, vote.round) {
+            true => self.validators.clone(),
+            false => vec![vote.voter.clone()],
+        };
+        let mut refused = None;
+        for voter in voters {
+            if let Some(equivocation) = self.find_skipped_round(&voter, vote.height) {
+                let skipped = equivocation.second.clone();
+                let message = self.record_evidence(equivocation);
+                if let Some(votes) = self
+                    .heights
+                    .get_mut(&skipped.height)
+                    .and_then(|rounds| rounds.get_mut(&skipped.round))
+                {
+                    votes.remove(&skipped.voter);
+                }
+                if skipped == *vote {
+                    refused = Some(message);
+                }
+            }
+        }
+        match refused {
+            Some(message) => Err(message),
+            None => Ok(true),
+        }
+    }
+
+    // The first round at the height that is not over yet.
+    fn current_round(&self, height: u64) -> u64 {
+        let mut round = 0;
+        while self.is_round_over(height, round) {
+            round += 1;
+        }
+        round
+    }
+
+    fn has_voted(&self, voter: &str, height: u64, round: u64) -> bool {
+        self.round_votes(height, round)
+            .is_some_and(|votes| votes.contains_key(voter))
+    }
+
+    // Counts only votes cast for the height the block is at, and needs the quorum within one round.
+    fn has_quorum(&self, block_hash: &str, height: u64) -> bool {
+        self.quorum > 0
+            && self.heights.get(&height).is_some_and(|rounds| {
+                rounds
+                    .keys()
+                    .any(|round| self.count_in_round(block_hash, height, *round) >= self.quorum)
+            })
+    }
+
+    // Votes up to the finalized height can no longer change anything.
+    fn prune(&mut self, finalized_height: u64) {
+        self.heights = self.heights.split_off(&(finalized_height + 1));
+    }
+
+    fn get_evidence(&self) -> Vec<Equivocation> {
+        self.evidence.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup(count: usize) -> (Vec<Address>, VoteBook) {
+        let validators: Vec<Address> = (0..count).map(|_| Address::new()).collect();
+        let keys = validators
+            .iter()
+            .map(|validator| validator.get_public_address().to_string())
+            .collect();
+        let quorum = count - count.saturating_sub(1) / 3;
+        (validators, VoteBook::new(keys, quorum))
+    }
+
+    fn approve(validator: &Address, block_hash: &str, height: u64, round: u64) -> BlockVerifyTx {
+        BlockVerifyTx::new(1, block_hash.to_string(), height, round, true, validator)
+    }
+
+    #[test]
+    fn quorum_of_approvals_finalizes() {
+        let (validators, mut book) = setup(4);
+        for validator in &validators[..2] {
+            assert_eq!(book.add_vote(&approve(validator, "a", 5, 0)), Ok(true));
+        }
+        assert!(!book.has_quorum("a", 5));
+
+        assert_eq!(book.add_vote(&approve(&validators[2], "a", 5, 0)), Ok(true));
+        assert!(book.has_quorum("a", 5));
+        // Votes cast for another height do not count towards the block.
+        assert!(!book.has_quorum("a", 6));
+    }
+
+    #[test]
+    fn repeated_and_rejecting_votes_are_not_counted() {
+        let (validators, mut book) = setup(4);
+        let vote = approve(&validators[0], "a", 5, 0);
+        assert_eq!(book.add_vote(&vote), Ok(true));
+        assert_eq!(book.add_vote(&vote), Ok(false));
+
+        let rejection = BlockVerifyTx::new(1, "b".to_string(), 5, 0, false, &validators[1]);
+        assert_eq!(book.add_vote(&rejection), Ok(false));
+        assert_eq!(book.count_in_round("a", 5, 0) + book.count_in_round("b", 5, 0), 1);
+        assert!(book.get_evidence().is_empty());
+    }
+
+    #[test]
+    fn conflicting_blocks_cannot_both_reach_quorum() {
+        let (validators, mut book) = setup(4);
+        // Two blocks at the same height are broadcast; half the validators see each one first.
+        for validator in &validators[..2] {
+            assert_eq!(book.add_vote(&approve(validator, "a", 5, 0)), Ok(true));
+        }
+        for validator in &validators[2..] {
+            assert_eq!(book.add_vote(&approve(validator, "b", 5, 0)), Ok(true));
+        }
+        // Every validator voted and neither block has a quorum, so the round is over.
+        assert_eq!(book.current_round(5), 1);
+
+        // Approving the other block as well in the same round is refused.
+        for (validator, other) in [(&validators[0], "b"), (&validators[2], "a")] {
+            assert!(book.add_vote(&approve(validator, other, 5, 0)).is_err());
+        }
+        assert!(!book.has_quorum("a", 5));
+        assert!(!book.has_quorum("b", 5));
+        assert_eq!(book.get_evidence().len(), 2);
+    }
+
+    #[test]
+    fn split_round_gives_way_to_the_next() {
+        let (validators, mut book) = setup(3);
+        assert_eq!(book.add_vote(&approve(&validators[0], "a", 5, 0)), Ok(true));
+        assert_eq!(book.add_vote(&approve(&validators[1], "b", 5, 0)), Ok(true));
+        assert_eq!(book.current_round(5), 1);
+
+        for validator in &validators {
+            assert_eq!(book.add_vote(&approve(validator, "a", 5, 1)), Ok(true));
+        }
+        assert!(book.has_quorum("a", 5));
+        assert!(!book.has_quorum("b", 5));
+        assert!(book.get_evidence().is_empty());
+    }
+
+    #[test]
+    fn fully_voted_split_round_gives_way_to_the_next() {
+        let (validators, mut book) = setup(4);
+        for (validator, block_hash) in validators.iter().zip(["a", "a", "b"]) {
+            assert_eq!(book.add_vote(&approve(validator, block_hash, 5, 0)), Ok(true));
+        }
+        // The last validator could still complete the quorum of the leading block.
+        assert_eq!(book.current_round(5), 0);
+
+        assert_eq!(book.add_vote(&approve(&validators[3], "c", 5, 0)), Ok(true));
+        assert_eq!(book.current_round(5), 1);
+        for validator in &validators {
+            assert_eq!(book.add_vote(&approve(validator, "b", 5, 1)), Ok(true));
+        }
+        assert!(book.has_quorum("b", 5));
+        assert!(book.get_evidence().is_empty());
+    }
+
+    #[test]
+    fn approving_past_a_quorum_round_is_equivocation() {
+        let (validators, mut book) = setup(4);
+        for validator in &validators[..3] {
+            assert_eq!(book.add_vote(&approve(validator, "a", 5, 0)), Ok(true));
+        }
+        assert!(book.has_quorum("a", 5));
+
+        // Moving to a later round that never started does not hide a second block.
+        assert!(book.add_vote(&approve(&validators[0], "b", 5, 1)).is_err());
+        assert_eq!(book.get_evidence().len(), 1);
+        assert!(!book.has_voted(validators[0].get_public_address(), 5, 1));
+    }
+
+    #[test]
+    fn quorum_exposes_votes_cast_in_later_rounds() {
+        let (validators, mut book) = setup(4);
+        assert_eq!(book.add_vote(&approve(&validators[0], "a", 5, 0)), Ok(true));
+        assert_eq!(book.add_vote(&approve(&validators[0], "b", 5, 1)), Ok(true));
+        for validator in &validators[1..3] {
+            assert_eq!(book.add_vote(&approve(validator, "a", 5, 0)), Ok(true));
+        }
+
+        assert_eq!(book.get_evidence().len(), 1);
+        assert_eq!(book.count_in_round("b", 5, 1), 0);
+    }
+
+    #[test]
+    fn votes_from_outside_the_set_or_forged_are_refused() {
+        let (validators, mut book) = setup(1);
+        assert!(book.add_vote(&approve(&Address::new(), "a", 5, 0)).is_err());
+
+        let mut forged = approve(&validators[0], "a", 5, 0);
+        forged.block_hash = "b".to_string();
+        assert!(book.add_vote(&forged).is_err());
+        assert!(!book.has_quorum("b", 5));
+    }
+
+    #[test]
+    fn pruning_drops_finalized_heights() {
+        let (validators, mut book) = setup(1);
+        let voter = validators[0].get_public_address();
+        assert_eq!(book.add_vote(&approve(&validators[0], "a", 5, 0)), Ok(true));
+        assert_eq!(book.add_vote(&approve(&validators[0], "b", 6, 0)), Ok(true));
+        book.prune(5);
+        assert!(!book.has_voted(voter, 5, 0));
+        assert!(book.has_voted(voter, 6, 0));
+    }
+}
@@ -2,8 +2,10 @@ use async_channel::{Receiver, Sender};
 
 use crate::mini_chain::{
     block::Block,
-    node::{BlockVerifyTx, GetNonExistingBlockTx, Node},
+    codec::{self, Decode, Encode},
+    node::{GetNonExistingBlockTx, Node},
     transaction::Transaction,
+    vote::BlockVerifyTx,
 };
 
 #[derive(Debug, Clone)]
@@ -66,17 +68,11 @@ impl ChannelConfigurer for Channels {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Network {
     pub channel: Channels,
-}
-
-impl Default for Network {
-    fn default() -> Self {
-        Self {
-            channel: Channels::default(),
-        }
-    }
+    // Chain of the first node joined; nodes of other chains are refused.
+    pub chain_id: Option<u64>,
 }
 
 pub trait NetworkConfigurer {
@@ -106,7 +102,21 @@ impl NetworkConfigurer for Network {
     }
 
     fn set_pipeline(&mut self, nodes: Vec<Node>) {
-        self.channel.set_pipeline(nodes);
+        let mut peers = vec![];
+        for node in nodes {
+            let chain_id = *self.chain_id.get_or_insert(node.chain_id);
+            if node.chain_id != chain_id {
+                println!(
+                    "Refusing node {} of chain {:016x} on network of chain {:016x}",
+                    node.address.get_public_address(),
+                    node.chain_id,
+                    chain_id
+                );
+                continue;
+            }
+            peers.push(node);
+        }
+        self.channel.set_pipeline(peers);
     }
 }
 
@@ -115,11 +125,25 @@ impl Network {
         receiver: Receiver<T>,
         senders: Vec<Sender<T>>,
     ) {
-        loop {
-            if let Ok(message) = receiver.recv().await {
-                for sender in &senders {
-                    let sender = sender.clone();
-                    sender.send(message.clone()).await.unwrap();
+        while let Ok(message) = receiver.recv().await {
+            for sender in &senders {
+                let sender = sender.clone();
+                sender.send(message.clone()).await.unwrap();
+            }
+        }
+    }
+
+    // Messages crossing the network travel in their canonical binary encoding.
+    async fn broadcast_encoded_message<T: Encode + Decode + Send + 'static>(
+        receiver: Receiver<T>,
+        senders: Vec<Sender<T>>,
+    ) {
+        while let Ok(message) = receiver.recv().await {
+            let bytes = codec::to_bytes(&message);
+            for sender in &senders {
+                match codec::from_bytes::<T>(&bytes) {
+                    Ok(message) => sender.send(message).await.unwrap(),
+                    Err(e) => println!("Failed decoding a network message:\n{:?}", e),
                 }
             }
         }
@@ -131,22 +155,33 @@ impl Network {
         senders: Vec<Sender<T>>,
     ) -> Result<(), String> {
         let broadcast_future = Self::broadcast_message(receiver, senders);
-        let _ = tokio::spawn(broadcast_future);
+        tokio::spawn(broadcast_future);
+
+        Ok(())
+    }
+
+    async fn run_encoded_broadcaster<T: Encode + Decode + Send + 'static>(
+        &self,
+        receiver: Receiver<T>,
+        senders: Vec<Sender<T>>,
+    ) -> Result<(), String> {
+        let broadcast_future = Self::broadcast_encoded_message(receiver, senders);
+        tokio::spawn(broadcast_future);
 
         Ok(())
     }
 
     pub async fn run_network(&mut self) -> Result<(), String> {
         let _ = tokio::try_join!(
-            self.run_broadcaster(
+            self.run_encoded_broadcaster(
                 self.channel.tx_receiver.clone(),
                 self.channel.node_tx_senders.clone()
             ),
-            self.run_broadcaster(
+            self.run_encoded_broadcaster(
                 self.channel.mined_block_receiver.clone(),
                 self.channel.node_mined_block_senders.clone()
             ),
-            self.run_broadcaster(
+            self.run_encoded_broadcaster(
                 self.channel.block_verify_tx_receiver.clone(),
                 self.channel.node_block_verify_tx_senders.clone()
             ),
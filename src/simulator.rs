@@ -1,6 +1,8 @@
 use crate::client::{Client, TxTriggerController};
 use crate::mini_chain::node::NodeController;
 use crate::mini_chain::{
+    address::Address,
+    genesis::GenesisConfig,
     metadata::{ChainMetaData, ChainMetaDataOperation},
     node::Node,
 };
@@ -8,26 +10,69 @@ use crate::network::{Network, NetworkConfigurer};
 use futures::future::try_join_all;
 
 pub async fn chain_simulation() {
-    let (node_count, client_count) = {
+    let (node_count, client_count, initial_balance, block_store_dir) = {
         let metadata = ChainMetaData::default();
         (
             metadata.get_node_count().unwrap(),
             metadata.get_client_count().unwrap(),
+            metadata.get_initial_balance().unwrap(),
+            metadata.get_block_store_dir().unwrap(),
         )
     };
 
     let mut network = Network::default();
 
+    // Keys are kept next to the block files. The genesis config is built from them, so it and the
+    // chain ID stay the same across restarts and the stored blocks stay valid.
+    let mut clients: Vec<Client> = vec![];
+    for index in 0..client_count {
+        let address = Address::load_or_create(&format!("{}/client-{}.key", block_store_dir, index))
+            .unwrap();
+        clients.push(Client::new(address, network.get_tx_sender()));
+    }
+
+    let client_addrs: Vec<String> = clients
+        .iter()
+        .map(|client| client.get_public_address().to_string())
+        .collect();
+    for client in clients.iter_mut() {
+        client.set_recipients(client_addrs.clone());
+    }
+
+    let allocations: Vec<(String, usize)> = client_addrs
+        .iter()
+        .map(|addr| (addr.clone(), initial_balance))
+        .collect();
+
+    // Every node validates, so the validator set is known before the nodes start.
+    let node_addresses: Vec<Address> = (0..node_count)
+        .map(|index| Address::load_or_create(&format!("{}/node-{}.key", block_store_dir, index)))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let validators: Vec<String> = node_addresses
+        .iter()
+        .map(|addr| addr.get_public_address().to_string())
+        .collect();
+    let genesis = GenesisConfig::new(allocations, validators).unwrap();
+
     let mut nodes: Vec<Node> = vec![];
-    for _ in 0..node_count {
+    for (index, address) in node_addresses.into_iter().enumerate() {
         let node = Node::new(
+            address,
             network.get_mined_block_sender(),
             network.get_block_verify_tx_sender(),
             network.get_non_existing_block_request_sender(),
+            genesis.clone(),
+            format!("{}/node-{}.blocks", block_store_dir, index),
         );
         nodes.push(node);
     }
 
+    // Clients follow their transactions through the nodes in turn.
+    for (client, node) in clients.iter_mut().zip(nodes.iter().cycle()) {
+        client.set_node(node.clone());
+    }
+
     let mut node_runners = Vec::new();
     for node in &nodes {
         let node = node.clone();
@@ -39,12 +84,6 @@ pub async fn chain_simulation() {
 
     network.set_pipeline(nodes.clone());
 
-    let mut clients: Vec<Client> = vec![];
-    for _ in 0..client_count {
-        let client = Client::new(network.get_tx_sender());
-        clients.push(client.clone());
-    }
-
     let mut client_runners = Vec::new();
     for client in &clients {
         let client = client.clone();
@@ -54,18 +93,30 @@ pub async fn chain_simulation() {
         });
     }
 
-    let _ = tokio::try_join!(
-        async {
-            try_join_all(client_runners).await?;
-            Ok::<(), String>(())
-        },
-        async {
-            network.run_network().await?;
-            Ok::<(), String>(())
-        },
-        async {
-            try_join_all(node_runners).await?;
-            Ok::<(), String>(())
-        },
-    );
+    // Runs until interrupted, then shuts the nodes down so they can pick up where they left off.
+    tokio::select! {
+        _ = async {
+            tokio::try_join!(
+                async {
+                    try_join_all(client_runners).await?;
+                    Ok::<(), String>(())
+                },
+                async {
+                    network.run_network().await?;
+                    Ok::<(), String>(())
+                },
+                async {
+                    try_join_all(node_runners).await?;
+                    Ok::<(), String>(())
+                },
+            )
+        } => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+
+    for node in &nodes {
+        if let Err(e) = node.shutdown().await {
+            println!("Failed shutting down a node:\n{:?}", e);
+        }
+    }
 }
@@ -0,0 +1,136 @@
+use mini_blockchain::mini_chain::{
+    block::{Block, BlockConfigurer},
+    codec::{self, Decode, Encode, CODEC_VERSION},
+    transaction::{Transaction, TxPayload},
+    vote::BlockVerifyTx,
+};
+use proptest::prelude::*;
+
+fn arb_payload() -> impl Strategy<Value = TxPayload> {
+    (".{0,64}", any::<usize>()).prop_map(|(addr, amount)| TxPayload { addr, amount })
+}
+
+fn arb_transaction() -> impl Strategy<Value = Transaction> {
+    (
+        any::<u64>(),
+        any::<usize>(),
+        any::<usize>(),
+        arb_payload(),
+        any::<usize>(),
+        ".{0,64}",
+        ".{0,128}",
+        ".{0,64}",
+    )
+        .prop_map(
+            |(chain_id, timestamp, nonce, payload, fee, signer, signature, hash)| Transaction {
+                chain_id,
+                timestamp,
+                nonce,
+                payload,
+                fee,
+                signer,
+                signature,
+                hash,
+            },
+        )
+}
+
+fn arb_block() -> impl Strategy<Value = Block> {
+    (
+        any::<u64>(),
+        proptest::option::of(".{0,64}"),
+        proptest::option::of(any::<u64>()),
+        proptest::collection::vec(arb_transaction(), 0..8),
+        0..16usize,
+        ".{0,64}",
+        proptest::collection::vec("[0-9a-f]{64}", 0..4),
+        ".{0,64}",
+    )
+        .prop_map(|(chain_id, builder, sequence, transactions, nonce, prev_hash, validators, hash)| {
+            let mut block = Block::default();
+            block.set_chain_id(chain_id);
+            if let Some(builder) = builder {
+                block.set_block_builder(builder);
+            }
+            if let Some(sequence) = sequence {
+                block.set_block_sequence(sequence);
+            }
+            for tx in transactions {
+                block.add_transaction(tx);
+            }
+            for _ in 0..nonce {
+                block.inc_nonce();
+            }
+            block.set_prev_hash(prev_hash);
+            block.set_validators(validators);
+            block.set_hash(hash);
+            block
+        })
+}
+
+fn round_trip<T: Encode + Decode>(value: &T) -> Result<T, TestCaseError> {
+    codec::from_bytes::<T>(&codec::to_bytes(value)).map_err(TestCaseError::fail)
+}
+
+proptest! {
+    #[test]
+    fn payload_round_trip(payload in arb_payload()) {
+        prop_assert_eq!(round_trip(&payload)?, payload);
+    }
+
+    #[test]
+    fn transaction_round_trip(tx in arb_transaction()) {
+        prop_assert_eq!(round_trip(&tx)?, tx);
+    }
+
+    #[test]
+    fn block_round_trip(block in arb_block()) {
+        prop_assert_eq!(round_trip(&block)?, block);
+    }
+
+    #[test]
+    fn block_verify_tx_round_trip(
+        chain_id in any::<u64>(),
+        block_hash in ".{0,64}",
+        height in any::<u64>(),
+        round in any::<u64>(),
+        verified in any::<bool>(),
+        voter in "[0-9a-f]{64}",
+        signature in "[0-9a-f]{128}",
+    ) {
+        let verify_tx = BlockVerifyTx { chain_id, block_hash, height, round, verified, voter, signature };
+        prop_assert_eq!(round_trip(&verify_tx)?, verify_tx);
+    }
+
+    #[test]
+    fn encoding_is_deterministic(tx in arb_transaction()) {
+        let decoded = round_trip(&tx)?;
+        prop_assert_eq!(codec::to_bytes(&decoded), codec::to_bytes(&tx));
+    }
+
+    #[test]
+    fn truncated_input_is_rejected(block in arb_block(), cut in any::<prop::sample::Index>()) {
+        let bytes = codec::to_bytes(&block);
+        let cut = cut.index(bytes.len());
+        prop_assert!(codec::from_bytes::<Block>(&bytes[..cut]).is_err());
+    }
+
+    #[test]
+    fn arbitrary_input_does_not_panic(bytes in proptest::collection::vec(any::<u8>(), 0..256)) {
+        let _ = codec::from_bytes::<Block>(&bytes);
+    }
+}
+
+#[test]
+fn unknown_version_is_rejected() {
+    let mut bytes = codec::to_bytes(&Transaction::new(0, String::new(), 1, 0, 0));
+    bytes[0] = CODEC_VERSION + 1;
+    assert!(codec::from_bytes::<Transaction>(&bytes).is_err());
+}
+
+#[test]
+fn trailing_bytes_are_rejected() {
+    let mut bytes = codec::to_bytes(&Transaction::new(0, String::new(), 1, 0, 0));
+    bytes.push(0);
+    assert!(codec::from_bytes::<Transaction>(&bytes).is_err());
+}